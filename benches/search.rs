@@ -0,0 +1,161 @@
+use connect4::caches::StateCaches;
+use connect4::engine::{evaluate_position_rec, evaluate_position_with_num_workers, evaluate_position_with_workers};
+use connect4::engine::{evaluate_position, evaluate_window, is_win, wdl, FULL_DEPTH, MAX_EVAL, MIN_EVAL, WIN_LENGTH};
+use connect4::state::State;
+use connect4::threats::sort_by_threats;
+use connect4::worker_threads::StateQueue;
+use std::hint::black_box;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::{Duration, Instant};
+
+
+const SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+const SAMPLES: usize = 10;
+
+
+// xorshift, only needs to be reproducible between runs
+fn next_random(seed: &mut u64) -> u64 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 7;
+    *seed ^= *seed << 17;
+    *seed
+}
+
+fn random_states(count: usize, seed: &mut u64) -> Vec<State> {
+    (0..count).map(|_| {
+        let mut state = State::start_state();
+        let plies = next_random(seed) % 30;
+
+        for _ in 0..plies {
+            let legal_moves = state.legal_moves();
+            if legal_moves.is_empty() {
+                break;
+            }
+
+            state = state.play_move(legal_moves[(next_random(seed) % legal_moves.len() as u64) as usize]);
+        }
+
+        state
+    }).collect()
+}
+
+// reports the fastest of SAMPLES runs, which is the least noisy figure on a busy machine
+fn bench<T>(name: &str, iterations: usize, mut f: impl FnMut() -> T) {
+    let mut best = Duration::MAX;
+
+    for _ in 0..SAMPLES {
+        let time = Instant::now();
+
+        for _ in 0..iterations {
+            black_box(f());
+        }
+
+        best = best.min(time.elapsed());
+    }
+
+    println!("{name:<32} {:>12.1?} / iter", best / iterations as u32);
+}
+
+fn main() {
+    let mut seed = SEED;
+
+    let boards: Vec<u64> = random_states(1024, &mut seed).iter()
+        .flat_map(|state| [state.curr_pieces, state.opp_pieces])
+        .collect();
+
+    bench("is_win", 100, || {
+        boards.iter().filter(|&&pieces| is_win(black_box(pieces), WIN_LENGTH)).count()
+    });
+
+    let threat_vectors: Vec<u32> = (0..1024)
+        .map(|_| (0..7).fold(0, |threats, col| threats | ((next_random(&mut seed) % 4) as u32) << (col << 2)))
+        .collect();
+
+    bench("sort_by_threats", 100, || {
+        threat_vectors.iter().map(|&threats| sort_by_threats(black_box(threats))).fold(0, |a, b| a ^ b)
+    });
+
+    // seeding 63 workers, by generating every state to the depth and by taking them lazily
+    for depth in [3, 5, 7] {
+        bench(&format!("generate_states depth {depth}"), 1, || State::start_state().generate_states(depth).len());
+        bench(&format!("states_iter depth {depth}, 63"), 1, || State::start_state().states_iter(depth).take(63).count());
+    }
+
+    // the database build's 30 workers taking states off the queue with nothing else to do, so every claim
+    // contends with the others
+    let frontier: Vec<State> = random_states(1 << 10, &mut seed).into_iter().cycle().take(1 << 18).collect();
+    let mut queues: Vec<StateQueue> = (0..SAMPLES).map(|_| StateQueue::new(frontier.clone())).collect();
+
+    bench("StateQueue drain, 30 threads", 1, || {
+        let states = queues.pop().unwrap();
+
+        thread::scope(|scope| {
+            for _ in 0..30 {
+                scope.spawn(|| {
+                    while let Some(state) = states.pop() {
+                        black_box(state);
+                        states.complete();
+                    }
+                });
+            }
+        });
+
+        states.completed()
+    });
+
+    let mid_game = State::encode(vec![
+        "       ",
+        "       ",
+        "   X   ",
+        "   O   ",
+        "  XOX  ",
+        "  OXO  ",
+        " XOXOX ",
+    ]);
+
+    let search = |caches: &mut StateCaches| evaluate_position_rec(
+        mid_game.curr_pieces,
+        mid_game.opp_pieces,
+        mid_game.height_map,
+        mid_game.moves_made,
+        MIN_EVAL,
+        MAX_EVAL,
+        FULL_DEPTH,
+        caches,
+        &AtomicBool::new(false),
+        &mut false,
+        &mut 0,
+    );
+
+    bench("evaluate_position_rec", 1, || search(&mut StateCaches::new()));
+
+    // the cache index is taken modulo the table length, so a table sized at runtime goes through the same path
+    bench("evaluate_position_rec 2^16 slots", 1, || search(&mut StateCaches::with_capacity((1 << 16) + 1)));
+
+    bench("evaluate_position_with_workers", 1, || evaluate_position_with_workers(mid_game.clone()));
+
+    // every worker shares the end game caches with the main search
+    bench("evaluate_position 4 workers", 1, || evaluate_position_with_num_workers(mid_game.clone(), 4));
+
+    bench("wdl", 1, || wdl(&mid_game));
+
+    // lost for the side to move, where wdl's one [-1, 1] window is compared with the exact eval and with two null
+    // window probes at 0
+    let lost = State::encode(vec![
+        "       ",
+        "       ",
+        "       ",
+        "   X   ",
+        "  XO   ",
+        "  OXO  ",
+        " XOXOX ",
+    ]);
+
+    bench("evaluate_position lost", 1, || evaluate_position(&lost));
+    bench("wdl lost", 1, || wdl(&lost));
+    bench("null window probes lost", 1, || match evaluate_window(&lost, 0, 1) {
+        Ok(eval) if eval <= 0 => evaluate_window(&lost, -1, 0),
+        eval => eval,
+    });
+}
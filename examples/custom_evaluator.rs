@@ -0,0 +1,40 @@
+use connect4::engine::{evaluate_position_with, DefaultEvaluator, Evaluator, FULL_DEPTH};
+use connect4::error::Result;
+use connect4::state::{Board, State, COLS};
+
+
+// knows nothing about threats: every unfinished position looks even, and columns are always tried in the
+// base order; a learned evaluator would slot in the same way
+struct NaiveEvaluator;
+
+impl Evaluator for NaiveEvaluator {
+    fn static_eval<B: Board>(&self, _board: B, _curr_pieces: u64, _opp_pieces: u64, _height_map: u64, _: i8) -> i8 {
+        0
+    }
+
+    fn order_moves<B: Board>(
+        &self,
+        _board: B,
+        _curr_pieces: u64,
+        _opp_pieces: u64,
+        _height_map: u64,
+        move_order: u32,
+        _history: &[u32; COLS as usize],
+    ) -> u32 {
+        move_order
+    }
+}
+
+// at a shallow depth the two evaluators can disagree, searched to the end they can't
+fn main() -> Result<()> {
+    let state = State::from_notation("/X/OOX/XXOOX/OOX/X/ O")?;
+
+    for depth in [4, FULL_DEPTH] {
+        let default_eval = evaluate_position_with(&state, depth, &DefaultEvaluator)?;
+        let naive_eval = evaluate_position_with(&state, depth, &NaiveEvaluator)?;
+
+        println!("Depth {depth}: default {default_eval:?}, naive {naive_eval:?}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,51 @@
+/* The C ABI of the connect4 crate, built with the ffi feature:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * A position is an opaque pointer from c4_new, owned by the caller until it's passed to c4_free. Every other
+ * function needs a live pointer from c4_new, and a state must not be used from two threads at once. Columns
+ * count from 0 on the left, and X moves first. A function that fails on a bug in the library rather than on its
+ * input returns C4_PANIC, or NULL from c4_new, and leaves the state as it was.
+ */
+#ifndef CONNECT4_H
+#define CONNECT4_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define C4_OK 0
+#define C4_ILLEGAL_MOVE 1
+#define C4_PANIC (-2)
+
+#define C4_NO_MOVE (-1)
+
+#define C4_ONGOING 0
+#define C4_X_WON 1
+#define C4_O_WON 2
+#define C4_DRAW 3
+
+typedef struct C4State C4State;
+
+/* the empty board with X to move, or NULL */
+C4State *c4_new(void);
+
+/* frees a state from c4_new, NULL is ignored */
+void c4_free(C4State *state);
+
+/* C4_OK, or C4_ILLEGAL_MOVE for a full column, a column off the board or a move once the game is over, which
+ * leave the state as it was, or C4_PANIC */
+int c4_play(C4State *state, int col);
+
+/* the column to play, or C4_NO_MOVE once the game is over or if the search fails; positions the opening book
+ * doesn't have are solved in full, with the databases in the working directory when there are any, or C4_PANIC */
+int c4_best_move(const C4State *state);
+
+/* C4_ONGOING, C4_X_WON, C4_O_WON, C4_DRAW or C4_PANIC */
+int c4_outcome(const C4State *state);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
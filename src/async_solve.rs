@@ -0,0 +1,26 @@
+use crate::cancellation::CancellationToken;
+use crate::engine::{solve_until, Solution};
+use crate::error::{Connect4Error, Result};
+use crate::state::State;
+
+
+// cancels the search when the future holding it is dropped, finished or not
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+// runs solve on tokio's blocking pool so the executor's threads stay free; a blocking task can't be aborted,
+// so without the cancellation token a dropped future would leave the search and its workers running to completion
+// for a result nobody reads, instead dropping the future stops them within a few nodes
+pub async fn solve_async(state: State) -> Result<Solution> {
+    let cancel = CancellationToken::new();
+    let _cancel_on_drop = CancelOnDrop(cancel.clone());
+
+    tokio::task::spawn_blocking(move || solve_until(&state, &cancel))
+        .await
+        .map_err(|err| Connect4Error::WorkerThreadPanic(err.to_string()))?
+}
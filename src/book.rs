@@ -0,0 +1,266 @@
+use crate::caches::StateCaches;
+use crate::database::read_databases_into_caches;
+use crate::engine::optimal_moves;
+use crate::error::{Connect4Error, Result};
+use crate::state::{State, COLS, ROWS};
+use log::debug;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+
+const OPENING_BOOK_NAME: &str = "opening_book.bin";
+// canonical state followed by a bitmask of its best columns
+const BOOK_ENTRY_BYTES: usize = 9;
+const BOOK_MAGIC: [u8; 4] = *b"C4BK";
+const BOOK_VERSION: u16 = 1;
+// magic, version, rows, cols, entry count
+const BOOK_HEADER_BYTES: usize = 4 + 2 + 1 + 1 + 8;
+// the states and the column bitmasks both depend on the board size
+const BOOK_LAYOUT: [u8; 2] = [ROWS as u8, COLS as u8];
+
+static OPENING_BOOK: OnceLock<HashMap<u64, Vec<u32>>> = OnceLock::new();
+
+
+// the book is keyed by the canonical state, so its moves are mirrored whenever the state is the reflected one
+fn orient_moves(state: &State, moves: &[u32]) -> Vec<u32> {
+    if (state.curr_pieces | state.height_map) == state.to_bitboard() {
+        moves.to_vec()
+    } else {
+        moves.iter().rev().map(|&col| COLS - 1 - col).collect()
+    }
+}
+
+fn add_book_moves(
+    state: State,
+    depth: usize,
+    caches: &mut StateCaches,
+    book: &mut HashMap<u64, Vec<u32>>,
+) -> Result<()> {
+    if state.moves_made as usize >= depth || state.is_terminal() || book.contains_key(&state.to_bitboard()) {
+        return Ok(());
+    }
+
+    let mut best_moves = optimal_moves(&state, caches)?.best_moves;
+    best_moves.sort();
+
+    book.insert(state.to_bitboard(), orient_moves(&state, &best_moves));
+
+    for best_move in best_moves {
+        for next_state in state.play_move(best_move).next_states() {
+            add_book_moves(next_state, depth, caches, book)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid_book(filename: &str, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{filename}: {reason}"))
+}
+
+// the same header as the databases, with the book's own magic; entries are written sorted by state, so the same
+// book always makes the same file whatever order the map holds them in
+fn write_opening_book(path: &Path, book: &HashMap<u64, Vec<u32>>) -> io::Result<()> {
+    let mut entries: Vec<(&u64, &Vec<u32>)> = book.iter().collect();
+    entries.sort_unstable_by_key(|&(state, _)| state);
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&BOOK_MAGIC)?;
+    writer.write_all(&BOOK_VERSION.to_le_bytes())?;
+    writer.write_all(&BOOK_LAYOUT)?;
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+
+    for (state, best_moves) in entries {
+        let best_cols = best_moves.iter().fold(0u8, |cols, &col| cols | (1 << col));
+
+        writer.write_all(&state.to_le_bytes())?;
+        writer.write_all(&[best_cols])?;
+    }
+
+    writer.flush()
+}
+
+fn read_opening_book(path: &Path) -> Result<HashMap<u64, Vec<u32>>> {
+    let filename = path.display().to_string();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0; BOOK_HEADER_BYTES];
+
+    reader.read_exact(&mut header).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_book(&filename, "truncated header"),
+        _ => err,
+    })?;
+
+    let (magic, header) = header.split_at(BOOK_MAGIC.len());
+    let (version, header) = header.split_at(2);
+    let (layout, entry_count) = header.split_at(BOOK_LAYOUT.len());
+
+    if magic != BOOK_MAGIC {
+        return Err(invalid_book(&filename, "not an opening book").into());
+    }
+
+    let version = u16::from_le_bytes(version.try_into().unwrap());
+    if version != BOOK_VERSION {
+        return Err(Connect4Error::DatabaseVersionMismatch(
+            format!("{filename} has format version {version}, expected {BOOK_VERSION}")));
+    }
+
+    if layout != BOOK_LAYOUT {
+        return Err(Connect4Error::DatabaseVersionMismatch(
+            format!("{filename} was written with layout {layout:?}, expected {BOOK_LAYOUT:?}")));
+    }
+
+    let entry_count = u64::from_le_bytes(entry_count.try_into().unwrap());
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+
+    if entry_count.checked_mul(BOOK_ENTRY_BYTES as u64) != Some(buffer.len() as u64) {
+        return Err(invalid_book(&filename, "entry count does not match the file size").into());
+    }
+
+    Ok(buffer.chunks_exact(BOOK_ENTRY_BYTES).map(|entry| {
+        let (state, best_cols) = entry.split_at(8);
+        let best_moves = (0..COLS).filter(|&col| (best_cols[0] >> col) & 1 == 1).collect();
+
+        (u64::from_le_bytes(state.try_into().unwrap()), best_moves)
+    }).collect())
+}
+
+// covers every position up to depth plies that can come up while the book side plays optimally, for
+// either side, so the replies to all opponent moves are included
+pub fn export_opening_book(depth: usize) -> Result<()> {
+    let mut caches = StateCaches::new();
+
+    read_databases_into_caches(&mut caches)?;
+
+    let start = State::start_state();
+    let mut book = HashMap::new();
+
+    add_book_moves(start.clone(), depth, &mut caches, &mut book)?;
+
+    for next_state in start.next_states() {
+        add_book_moves(next_state, depth, &mut caches, &mut book)?;
+    }
+
+    debug!("Opening Book Entries: {}", book.len());
+
+    write_opening_book(Path::new(OPENING_BOOK_NAME), &book)?;
+
+    Ok(())
+}
+
+pub fn lookup_book(state: &State) -> Option<Vec<u32>> {
+    let book = OPENING_BOOK.get_or_init(|| {
+        read_opening_book(Path::new(OPENING_BOOK_NAME)).unwrap_or_else(|err| {
+            debug!("Opening Book Unavailable: {err}");
+            HashMap::new()
+        })
+    });
+
+    book.get(&state.to_bitboard()).map(|best_moves| orient_moves(state, best_moves))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // a file of its own per test under the temp dir, removed when the test is done with it
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("connect4-{}-{name}.bin", std::process::id())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn book(entries: &[(&[u32], &[u32])]) -> HashMap<u64, Vec<u32>> {
+        entries.iter()
+            .map(|&(moves, best_moves)| (State::from_moves(moves).unwrap().to_bitboard(), best_moves.to_vec()))
+            .collect()
+    }
+
+    fn written(name: &str, book: &HashMap<u64, Vec<u32>>) -> Vec<u8> {
+        let file = TempFile::new(name);
+        write_opening_book(&file.0, book).unwrap();
+
+        fs::read(&file.0).unwrap()
+    }
+
+    fn assert_invalid_data(result: Result<HashMap<u64, Vec<u32>>>, reason: &str) {
+        match result {
+            Err(Connect4Error::DatabaseIOError(err)) if err.kind() == io::ErrorKind::InvalidData => {
+                assert!(err.to_string().ends_with(reason), "expected {reason:?}, got {err}");
+            },
+            result => panic!("expected invalid data ({reason}), got {result:?}"),
+        }
+    }
+
+    fn read(name: &str, bytes: &[u8]) -> Result<HashMap<u64, Vec<u32>>> {
+        let file = TempFile::new(name);
+        fs::write(&file.0, bytes).unwrap();
+
+        read_opening_book(&file.0)
+    }
+
+    const ENTRIES: [(&[u32], &[u32]); 4] = [(&[], &[3]), (&[3], &[3]), (&[2], &[2, 3]), (&[3, 3], &[0, 6])];
+
+    #[test]
+    fn the_book_reads_back_as_written() {
+        let book = book(&ENTRIES);
+
+        assert_eq!(read("book-round-trip", &written("book-round-trip-source", &book)).unwrap(), book);
+    }
+
+    #[test]
+    fn the_same_book_always_writes_the_same_file() {
+        let mut reversed = ENTRIES;
+        reversed.reverse();
+
+        let bytes = written("book-forwards", &book(&ENTRIES));
+        let states: Vec<u64> = bytes[BOOK_HEADER_BYTES..].chunks_exact(BOOK_ENTRY_BYTES)
+            .map(|entry| u64::from_le_bytes(entry[..8].try_into().unwrap()))
+            .collect();
+
+        assert_eq!(bytes, written("book-backwards", &book(&reversed)));
+        assert!(states.is_sorted());
+    }
+
+    #[test]
+    fn a_corrupt_header_is_rejected() {
+        let bytes = written("book-header", &book(&ENTRIES));
+
+        // the headerless format the book was first written in
+        assert_invalid_data(read("book-headerless", &bytes[BOOK_HEADER_BYTES..]), "not an opening book");
+        assert_invalid_data(read("book-empty", &[]), "truncated header");
+
+        let mut bad_version = bytes.clone();
+        bad_version[BOOK_MAGIC.len()] ^= 0xff;
+        assert!(matches!(read("book-version", &bad_version), Err(Connect4Error::DatabaseVersionMismatch(_))));
+
+        let mut bad_layout = bytes.clone();
+        bad_layout[BOOK_MAGIC.len() + 2] ^= 0xff;
+        assert!(matches!(read("book-layout", &bad_layout), Err(Connect4Error::DatabaseVersionMismatch(_))));
+    }
+
+    #[test]
+    fn truncated_entries_do_not_match_the_count() {
+        let bytes = written("book-entries", &book(&ENTRIES));
+
+        assert_invalid_data(read("book-truncated", &bytes[..bytes.len() - 1]), "does not match the file size");
+        assert_invalid_data(
+            read("book-missing", &bytes[..bytes.len() - BOOK_ENTRY_BYTES]),
+            "does not match the file size",
+        );
+    }
+}
@@ -1,74 +1,405 @@
 use std::cmp::{max, min};
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
 use dashmap::DashMap;
 use crate::engine::*;
 use crate::state::*;
+use crate::{col_shift, create_cache_entry, get_cache_entry_eval, get_cache_entry_state};
 
 
-pub const CACHE_VALUE_SHIFT: u8 = 56;
 pub const BEGINNING_GAME_CACHE_DEPTH: i8 = 24;
 pub const CACHE_SIZE: usize = (1 << 19) + 1;
+// a bounded beginning game cache that goes over capacity is cut down to this share of it, so it isn't swept
+// again on the very next insert
+const BEG_GAME_EVICTION_NUMERATOR: usize = 3;
+const BEG_GAME_EVICTION_DENOMINATOR: usize = 4;
+const ZOBRIST_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+// zobrist keys a column at a time: ZOBRIST_KEYS[col][bits] is the xor of the keys of every square set in bits
+const ZOBRIST_KEYS: [[u64; 1 << COL_BITS]; COLS as usize] = zobrist_keys();
 
 
 pub struct StateCaches {
     pub beg_game_lower_bound_cache: Arc<DashMap<u64, i8>>,
     pub beg_game_upper_bound_cache: Arc<DashMap<u64, i8>>,
-    pub end_game_lower_bound_cache: Vec<u64>,
-    pub end_game_upper_bound_cache: Vec<u64>,
+    pub end_game_lower_bound_cache: Arc<[AtomicU64]>,
+    pub end_game_upper_bound_cache: Arc<[AtomicU64]>,
+    pub end_game_lower_bound_deep_cache: Arc<[AtomicU64]>,
+    pub end_game_upper_bound_deep_cache: Arc<[AtomicU64]>,
+    pub end_game_lower_bound_work: Arc<[AtomicU8]>,
+    pub end_game_upper_bound_work: Arc<[AtomicU8]>,
+    // exact evals of solved positions, looked up before the end game caches for positions with at least
+    // tablebase_min_moves moves made; i8::MAX moves means there is no tablebase
+    pub tablebase: Arc<DashMap<u64, i8>>,
+    pub tablebase_min_moves: i8,
+    // per thread, the last move to cause a beta cutoff at each ply
+    pub killer_moves: [u64; MAX_TOTAL_MOVES as usize],
+    // per thread, how many beta cutoffs each column has caused at each ply over the whole search
+    pub history: [[u32; COLS as usize]; MAX_TOTAL_MOVES as usize],
+    // the order columns are tried in before any threats, killers or history reorder them
+    pub move_order: u32,
+    // set for searches over a ReadOnlyCaches, put_lower_bound and put_upper_bound then leave the shared
+    // beginning game caches alone and only fill the search's own end game caches
+    pub read_only: bool,
+    // index the end game caches by the zobrist hash of the state instead of the state itself, the full state
+    // is still what an entry is checked against
+    pub zobrist_index: bool,
+    // the most entries each beginning game cache holds before the deepest positions are evicted, None (the
+    // default) never evicts, which is what a database build wants; the shallowest positions stay even past it
+    pub beg_game_capacity: Option<usize>,
+    // positions with at most this many moves made go in the beginning game caches, the rest in the end game caches;
+    // BEGINNING_GAME_CACHE_DEPTH by default
+    pub beg_game_depth: i8,
+    // shared by every thread searching these caches, and empty unless the stats feature is on
+    cache_counters: Arc<CacheCounters>,
+    // how far each beginning game cache may grow before it's swept again, see evict_over_capacity
+    beg_game_lower_bound_eviction_mark: Arc<AtomicUsize>,
+    beg_game_upper_bound_eviction_mark: Arc<AtomicUsize>,
+}
+
+// counted since the caches were made or last cleared, across every thread sharing them; a probe that finds
+// nothing in its slot is a miss, and a collision is a put whose deep end game slot held a different position,
+// which an overwrite then replaced in either slot
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct CacheStats {
+    pub beg_game_hits: u64,
+    pub end_game_hits: u64,
+    pub tablebase_hits: u64,
+    pub misses: u64,
+    pub end_game_collisions: u64,
+    pub end_game_overwrites: u64,
+}
+
+#[derive(Default)]
+struct CacheCounters {
+    #[cfg(feature = "stats")]
+    counts: [AtomicU64; CacheCounter::COUNT],
+}
+
+#[derive(Clone, Copy)]
+enum CacheCounter {
+    BegGameHit,
+    EndGameHit,
+    TablebaseHit,
+    Miss,
+    EndGameCollision,
+    EndGameOverwrite,
+}
+
+// which side of a position's eval probe knows, an eval only bounded on both sides is an Interval
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+    Interval,
+}
+
+// the eval lies in [lower, upper], a side with nothing cached is MIN_EVAL or MAX_EVAL
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ProbeResult {
+    pub lower: i8,
+    pub upper: i8,
+    pub bound: Bound,
+}
+
+// a fully built database shared between query threads, which only ever read it; each query searches with its
+// own search_caches, which read the shared beginning game caches and tablebase but never write to them
+pub struct ReadOnlyCaches {
+    caches: StateCaches,
 }
 
 
 #[macro_export]
 macro_rules! cache_index {
-    ($state:expr) => {
-        $state as usize % CACHE_SIZE
+    ($caches:expr, $state:expr) => {
+        $caches.cache_index($state)
     };
 }
 
-#[macro_export]
-macro_rules! get_cache_entry_eval {
-    ($cache_entry:expr) => {
-        ($cache_entry >> CACHE_VALUE_SHIFT) as i8 - MAX_PLAYER_MOVES
+
+// the search reaches the caches through these, each only the inherent method of the same name
+impl SearchCaches for StateCaches {
+    #[inline(always)]
+    fn move_order(&self) -> u32 {
+        StateCaches::move_order(self)
     }
-}
 
-#[macro_export]
-macro_rules! get_cache_entry_state {
-    ($cache_entry:expr) => {
-        ($cache_entry & BOARD_MASK)
+    #[inline(always)]
+    fn cache_index(&self, state: u64) -> usize {
+        StateCaches::cache_index(self, state)
     }
-}
 
-#[macro_export]
-macro_rules! create_cache_entry {
-    ($state:expr, $bound:expr) => {
-        $state | ((($bound + MAX_PLAYER_MOVES) as u64) << CACHE_VALUE_SHIFT)
-    };
+    #[inline(always)]
+    fn get_lower_bound(&self, state: u64, moves_made: i8, cache_index: usize) -> i8 {
+        StateCaches::get_lower_bound(self, state, moves_made, cache_index)
+    }
+
+    #[inline(always)]
+    fn get_upper_bound(&self, state: u64, moves_made: i8, cache_index: usize) -> i8 {
+        StateCaches::get_upper_bound(self, state, moves_made, cache_index)
+    }
+
+    #[inline(always)]
+    fn put_lower_bound(&mut self, bound: i8, state: u64, moves_made: i8, cache_index: usize, work: usize) {
+        StateCaches::put_lower_bound(self, bound, state, moves_made, cache_index, work)
+    }
+
+    #[inline(always)]
+    fn put_upper_bound(&mut self, bound: i8, state: u64, moves_made: i8, cache_index: usize, work: usize) {
+        StateCaches::put_upper_bound(self, bound, state, moves_made, cache_index, work)
+    }
+
+    #[inline(always)]
+    fn killer_move(&self, moves_made: i8) -> u64 {
+        StateCaches::killer_move(self, moves_made)
+    }
+
+    #[inline(always)]
+    fn put_killer_move(&mut self, next_move: u64, moves_made: i8) {
+        StateCaches::put_killer_move(self, next_move, moves_made)
+    }
+
+    #[inline(always)]
+    fn history(&self, moves_made: i8) -> &[u32; COLS as usize] {
+        StateCaches::history(self, moves_made)
+    }
+
+    #[inline(always)]
+    fn put_history(&mut self, col: u32, moves_made: i8) {
+        StateCaches::put_history(self, col, moves_made)
+    }
 }
 
+impl Default for StateCaches {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl StateCaches {
     pub fn from_beg_caches(
         beg_game_lower_bound_cache: Arc<DashMap<u64, i8>>,
         beg_game_upper_bound_cache: Arc<DashMap<u64, i8>>
     ) -> Self {
+        Self::from_beg_caches_with_capacity(beg_game_lower_bound_cache, beg_game_upper_bound_cache, CACHE_SIZE)
+    }
+
+    pub fn from_beg_caches_with_capacity(
+        beg_game_lower_bound_cache: Arc<DashMap<u64, i8>>,
+        beg_game_upper_bound_cache: Arc<DashMap<u64, i8>>,
+        cache_size: usize
+    ) -> Self {
+        assert!(cache_size > 0, "end game cache size must be positive");
+
         Self {
             beg_game_lower_bound_cache,
             beg_game_upper_bound_cache,
-            end_game_lower_bound_cache: vec![0; CACHE_SIZE],
-            end_game_upper_bound_cache: vec![0; CACHE_SIZE],
+            end_game_lower_bound_cache: (0..cache_size).map(|_| AtomicU64::new(0)).collect(),
+            end_game_upper_bound_cache: (0..cache_size).map(|_| AtomicU64::new(0)).collect(),
+            end_game_lower_bound_deep_cache: (0..cache_size).map(|_| AtomicU64::new(0)).collect(),
+            end_game_upper_bound_deep_cache: (0..cache_size).map(|_| AtomicU64::new(0)).collect(),
+            end_game_lower_bound_work: (0..cache_size).map(|_| AtomicU8::new(0)).collect(),
+            end_game_upper_bound_work: (0..cache_size).map(|_| AtomicU8::new(0)).collect(),
+            tablebase: Arc::new(DashMap::new()),
+            tablebase_min_moves: i8::MAX,
+            killer_moves: [0; MAX_TOTAL_MOVES as usize],
+            history: [[0; COLS as usize]; MAX_TOTAL_MOVES as usize],
+            move_order: DEFAULT_MOVE_ORDER,
+            read_only: false,
+            zobrist_index: false,
+            beg_game_capacity: None,
+            beg_game_depth: BEGINNING_GAME_CACHE_DEPTH,
+            cache_counters: Arc::default(),
+            beg_game_lower_bound_eviction_mark: Arc::default(),
+            beg_game_upper_bound_eviction_mark: Arc::default(),
         }
     }
 
     pub fn new() -> Self {
-        Self::from_beg_caches(Arc::new(DashMap::new()), Arc::new(DashMap::new()))
+        Self::with_capacity(CACHE_SIZE)
+    }
+
+    pub fn with_capacity(cache_size: usize) -> Self {
+        Self::from_beg_caches_with_capacity(Arc::new(DashMap::new()), Arc::new(DashMap::new()), cache_size)
     }
 
-    pub fn with_same_beg_caches(&self) -> Self {
-        Self::from_beg_caches(
+    // shares the beginning game caches and the tablebase, which any thread can write to, but allocates fresh end
+    // game caches of the same size: 34 bytes per slot (about 17MB at CACHE_SIZE) for each fork, and none of the
+    // fork's end game results are seen anywhere else
+    pub fn fork_shared_begin(&self) -> Self {
+        let fork = Self::from_beg_caches_with_capacity(
             self.beg_game_lower_bound_cache.clone(),
-            self.beg_game_upper_bound_cache.clone()
-        )
+            self.beg_game_upper_bound_cache.clone(),
+            self.cache_size()
+        ).with_move_order(self.move_order)
+            .with_tablebase(self.tablebase.clone(), self.tablebase_min_moves)
+            .with_zobrist_index(self.zobrist_index)
+            .with_beg_game_capacity(self.beg_game_capacity)
+            .with_beg_game_depth(self.beg_game_depth);
+
+        Self {
+            cache_counters: self.cache_counters.clone(),
+            beg_game_lower_bound_eviction_mark: self.beg_game_lower_bound_eviction_mark.clone(),
+            beg_game_upper_bound_eviction_mark: self.beg_game_upper_bound_eviction_mark.clone(),
+            ..fork
+        }
+    }
+
+    pub fn with_move_order(self, move_order: u32) -> Self {
+        Self { move_order, ..self }
+    }
+
+    pub fn with_tablebase(self, tablebase: Arc<DashMap<u64, i8>>, tablebase_min_moves: i8) -> Self {
+        Self { tablebase, tablebase_min_moves, ..self }
+    }
+
+    pub fn with_zobrist_index(self, zobrist_index: bool) -> Self {
+        Self { zobrist_index, ..self }
+    }
+
+    pub fn with_beg_game_capacity(self, beg_game_capacity: Option<usize>) -> Self {
+        Self { beg_game_capacity, ..self }
+    }
+
+    // a lower depth keeps the unbounded beginning game caches smaller, a higher one keeps more positions where no
+    // other position can overwrite them. The databases are the beginning game caches written out, so the depth
+    // they're built with is the deepest they hold; loaded with a lower depth, entries past it are never looked up
+    // but still take memory and get written back out, and loaded with a higher depth, the positions in between
+    // are searched again
+    pub fn with_beg_game_depth(self, beg_game_depth: i8) -> Self {
+        Self { beg_game_depth, ..self }
+    }
+
+    // shares every cache, so workers see each other's end game results as well (Lazy SMP); allocates nothing
+    // beyond the per thread killer and history tables, the end game slots are atomics that any thread can overwrite
+    pub fn with_shared_caches(&self) -> Self {
+        Self {
+            beg_game_lower_bound_cache: self.beg_game_lower_bound_cache.clone(),
+            beg_game_upper_bound_cache: self.beg_game_upper_bound_cache.clone(),
+            end_game_lower_bound_cache: self.end_game_lower_bound_cache.clone(),
+            end_game_upper_bound_cache: self.end_game_upper_bound_cache.clone(),
+            end_game_lower_bound_deep_cache: self.end_game_lower_bound_deep_cache.clone(),
+            end_game_upper_bound_deep_cache: self.end_game_upper_bound_deep_cache.clone(),
+            end_game_lower_bound_work: self.end_game_lower_bound_work.clone(),
+            end_game_upper_bound_work: self.end_game_upper_bound_work.clone(),
+            tablebase: self.tablebase.clone(),
+            tablebase_min_moves: self.tablebase_min_moves,
+            killer_moves: [0; MAX_TOTAL_MOVES as usize],
+            history: [[0; COLS as usize]; MAX_TOTAL_MOVES as usize],
+            move_order: self.move_order,
+            read_only: self.read_only,
+            zobrist_index: self.zobrist_index,
+            beg_game_capacity: self.beg_game_capacity,
+            beg_game_depth: self.beg_game_depth,
+            cache_counters: self.cache_counters.clone(),
+            beg_game_lower_bound_eviction_mark: self.beg_game_lower_bound_eviction_mark.clone(),
+            beg_game_upper_bound_eviction_mark: self.beg_game_upper_bound_eviction_mark.clone(),
+        }
+    }
+
+    // independent copies of every cache, nothing written to one is seen by the other; copies every entry, so it
+    // takes as much memory again as self, and entries other threads write to self meanwhile may or may not make it
+    pub fn deep_clone(&self) -> Self {
+        let copy_end_game_cache = |cache: &Arc<[AtomicU64]>| {
+            cache.iter().map(|entry| AtomicU64::new(entry.load(Ordering::Relaxed))).collect()
+        };
+        let copy_end_game_work = |work: &Arc<[AtomicU8]>| {
+            work.iter().map(|work| AtomicU8::new(work.load(Ordering::Relaxed))).collect()
+        };
+
+        Self {
+            beg_game_lower_bound_cache: Arc::new(self.beg_game_lower_bound_cache.as_ref().clone()),
+            beg_game_upper_bound_cache: Arc::new(self.beg_game_upper_bound_cache.as_ref().clone()),
+            end_game_lower_bound_cache: copy_end_game_cache(&self.end_game_lower_bound_cache),
+            end_game_upper_bound_cache: copy_end_game_cache(&self.end_game_upper_bound_cache),
+            end_game_lower_bound_deep_cache: copy_end_game_cache(&self.end_game_lower_bound_deep_cache),
+            end_game_upper_bound_deep_cache: copy_end_game_cache(&self.end_game_upper_bound_deep_cache),
+            end_game_lower_bound_work: copy_end_game_work(&self.end_game_lower_bound_work),
+            end_game_upper_bound_work: copy_end_game_work(&self.end_game_upper_bound_work),
+            tablebase: Arc::new(self.tablebase.as_ref().clone()),
+            tablebase_min_moves: self.tablebase_min_moves,
+            killer_moves: self.killer_moves,
+            history: self.history,
+            move_order: self.move_order,
+            read_only: self.read_only,
+            zobrist_index: self.zobrist_index,
+            beg_game_capacity: self.beg_game_capacity,
+            beg_game_depth: self.beg_game_depth,
+            cache_counters: Arc::new(self.cache_counters.copy()),
+            beg_game_lower_bound_eviction_mark: Arc::new(AtomicUsize::new(
+                self.beg_game_lower_bound_eviction_mark.load(Ordering::Relaxed)
+            )),
+            beg_game_upper_bound_eviction_mark: Arc::new(AtomicUsize::new(
+                self.beg_game_upper_bound_eviction_mark.load(Ordering::Relaxed)
+            )),
+        }
+    }
+
+    pub fn cache_size(&self) -> usize {
+        self.end_game_lower_bound_cache.len()
+    }
+
+    pub fn cache_index(&self, state: u64) -> usize {
+        let key = if self.zobrist_index { zobrist_hash(state) } else { state };
+
+        key as usize % self.cache_size()
+    }
+
+    pub fn clear(&mut self) {
+        self.beg_game_lower_bound_cache.clear();
+        self.beg_game_upper_bound_cache.clear();
+        self.beg_game_lower_bound_eviction_mark.store(0, Ordering::Relaxed);
+        self.beg_game_upper_bound_eviction_mark.store(0, Ordering::Relaxed);
+
+        for end_game_cache in [
+            &self.end_game_lower_bound_cache,
+            &self.end_game_upper_bound_cache,
+            &self.end_game_lower_bound_deep_cache,
+            &self.end_game_upper_bound_deep_cache,
+        ] {
+            end_game_cache.iter().for_each(|entry| entry.store(0, Ordering::Relaxed));
+        }
+
+        for end_game_work in [&self.end_game_lower_bound_work, &self.end_game_upper_bound_work] {
+            end_game_work.iter().for_each(|work| work.store(0, Ordering::Relaxed));
+        }
+
+        self.killer_moves = [0; MAX_TOTAL_MOVES as usize];
+        self.history = [[0; COLS as usize]; MAX_TOTAL_MOVES as usize];
+        self.cache_counters.reset();
+        // the tablebase only holds exact evals, so unlike the bounds it never goes stale
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> CacheStats {
+        let count = |counter| self.cache_counters.counts[counter as usize].load(Ordering::Relaxed);
+
+        CacheStats {
+            beg_game_hits: count(CacheCounter::BegGameHit),
+            end_game_hits: count(CacheCounter::EndGameHit),
+            tablebase_hits: count(CacheCounter::TablebaseHit),
+            misses: count(CacheCounter::Miss),
+            end_game_collisions: count(CacheCounter::EndGameCollision),
+            end_game_overwrites: count(CacheCounter::EndGameOverwrite),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let end_game_len: usize = [
+            &self.end_game_lower_bound_cache,
+            &self.end_game_upper_bound_cache,
+            &self.end_game_lower_bound_deep_cache,
+            &self.end_game_upper_bound_deep_cache,
+        ].iter().map(|end_game_cache| {
+            end_game_cache.iter().filter(|entry| entry.load(Ordering::Relaxed) != 0).count()
+        }).sum();
+
+        self.beg_game_lower_bound_cache.len() + self.beg_game_upper_bound_cache.len() + end_game_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub fn get_lower_bound(&self, state: u64, moves_made: i8, cache_index: usize) -> i8 {
@@ -78,6 +409,11 @@ impl StateCaches {
             cache_index,
             &self.beg_game_lower_bound_cache,
             &self.end_game_lower_bound_cache,
+            &self.end_game_lower_bound_deep_cache,
+            &self.tablebase,
+            self.tablebase_min_moves,
+            self.beg_game_depth,
+            &self.cache_counters,
             MIN_EVAL
         )
     }
@@ -89,61 +425,491 @@ impl StateCaches {
             cache_index,
             &self.beg_game_upper_bound_cache,
             &self.end_game_upper_bound_cache,
+            &self.end_game_upper_bound_deep_cache,
+            &self.tablebase,
+            self.tablebase_min_moves,
+            self.beg_game_depth,
+            &self.cache_counters,
             MAX_EVAL
         )
     }
 
+    pub fn move_order(&self) -> u32 {
+        self.move_order
+    }
+
+    pub fn killer_move(&self, moves_made: i8) -> u64 {
+        self.killer_moves[moves_made as usize]
+    }
+
+    pub fn put_killer_move(&mut self, next_move: u64, moves_made: i8) {
+        self.killer_moves[moves_made as usize] = next_move;
+    }
+
+    pub fn history(&self, moves_made: i8) -> &[u32; COLS as usize] {
+        &self.history[moves_made as usize]
+    }
+
+    pub fn put_history(&mut self, col: u32, moves_made: i8) {
+        let cutoffs = &mut self.history[moves_made as usize][col as usize];
+        *cutoffs = cutoffs.saturating_add(1);
+    }
+
     pub fn put_beg_game_lower_bound(&self, bound: i8, state: u64) {
         self.beg_game_lower_bound_cache.insert(state, bound);
     }
 
-    pub fn put_lower_bound(&mut self, bound: i8, state: u64, moves_made: i8, cache_index: usize) {
+    pub fn put_beg_game_upper_bound(&self, bound: i8, state: u64) {
+        self.beg_game_upper_bound_cache.insert(state, bound);
+    }
+
+    // only known once both bounds meet, as they do for every position the database generator has solved
+    pub fn get_beg_game_eval(&self, state: u64) -> Option<i8> {
+        let lower_bound = self.beg_game_lower_bound_cache.get(&state).map(|bound| *bound.value())?;
+        let upper_bound = self.beg_game_upper_bound_cache.get(&state).map(|bound| *bound.value())?;
+
+        (lower_bound == upper_bound).then_some(lower_bound)
+    }
+
+    // the tightest interval the caches hold for a canonical state, from whichever caches the search would read at
+    // moves_made; None when neither bound is cached. A bound of MIN_EVAL or MAX_EVAL says nothing, so it counts
+    // as not cached
+    pub fn probe(&self, state: u64, moves_made: i8) -> Option<ProbeResult> {
+        let cache_index = self.cache_index(state);
+        let lower = self.get_lower_bound(state, moves_made, cache_index);
+        let upper = self.get_upper_bound(state, moves_made, cache_index);
+
+        let bound = match (lower > MIN_EVAL, upper < MAX_EVAL) {
+            _ if lower == upper => Bound::Exact,
+            (true, true) => Bound::Interval,
+            (true, false) => Bound::Lower,
+            (false, true) => Bound::Upper,
+            (false, false) => return None,
+        };
+
+        Some(ProbeResult { lower, upper, bound })
+    }
+
+    pub fn get_tablebase_eval(&self, state: u64) -> Option<i8> {
+        self.tablebase.get(&state).map(|eval| *eval.value())
+    }
+
+    pub fn put_tablebase_eval(&mut self, eval: i8, state: u64, moves_made: i8) {
+        self.tablebase.insert(state, eval);
+        self.tablebase_min_moves = self.tablebase_min_moves.min(moves_made);
+    }
+
+    pub fn put_lower_bound(&mut self, bound: i8, state: u64, moves_made: i8, cache_index: usize, work: usize) {
+        if self.read_only && moves_made <= self.beg_game_depth {
+            return
+        }
+
         cache_put(
             bound,
             state,
             moves_made,
             cache_index,
+            work,
             &self.beg_game_lower_bound_cache,
-            &mut self.end_game_lower_bound_cache,
+            &self.end_game_lower_bound_cache,
+            &self.end_game_lower_bound_deep_cache,
+            &self.end_game_lower_bound_work,
+            self.beg_game_capacity,
+            &self.beg_game_lower_bound_eviction_mark,
+            self.beg_game_depth,
+            &self.cache_counters,
             max
         )
     }
 
-    pub fn put_upper_bound(&mut self, bound: i8, state: u64, moves_made: i8, cache_index: usize) {
+    pub fn put_upper_bound(&mut self, bound: i8, state: u64, moves_made: i8, cache_index: usize, work: usize) {
+        if self.read_only && moves_made <= self.beg_game_depth {
+            return
+        }
+
         cache_put(
             bound,
             state,
             moves_made,
             cache_index,
+            work,
             &self.beg_game_upper_bound_cache,
-            &mut self.end_game_upper_bound_cache,
+            &self.end_game_upper_bound_cache,
+            &self.end_game_upper_bound_deep_cache,
+            &self.end_game_upper_bound_work,
+            self.beg_game_capacity,
+            &self.beg_game_upper_bound_eviction_mark,
+            self.beg_game_depth,
+            &self.cache_counters,
             min
         )
     }
 }
 
-fn cache_get(state: u64, moves_made: i8, cache_index: usize, beg_game_cache: &Arc<DashMap<u64, i8>>, end_game_cache: &Vec<u64>, default_bound: i8) -> i8 {
-    if moves_made <= BEGINNING_GAME_CACHE_DEPTH {
+impl ReadOnlyCaches {
+    pub fn new(caches: StateCaches) -> Self {
+        Self { caches }
+    }
+
+    pub fn cache_size(&self) -> usize {
+        self.caches.cache_size()
+    }
+
+    pub fn get_lower_bound(&self, state: u64, moves_made: i8, cache_index: usize) -> i8 {
+        self.caches.get_lower_bound(state, moves_made, cache_index)
+    }
+
+    pub fn get_upper_bound(&self, state: u64, moves_made: i8, cache_index: usize) -> i8 {
+        self.caches.get_upper_bound(state, moves_made, cache_index)
+    }
+
+    pub fn probe(&self, state: u64, moves_made: i8) -> Option<ProbeResult> {
+        self.caches.probe(state, moves_made)
+    }
+
+    // end game caches for queries over these caches, allocated by fork_shared_begin (about 17MB at CACHE_SIZE) on
+    // every call; a thread running many queries can make one and pass it to evaluate_position_read_only_with each
+    // time, keeping what earlier queries found
+    pub fn search_caches(&self) -> StateCaches {
+        StateCaches { read_only: true, ..self.caches.fork_shared_begin() }
+    }
+}
+
+pub fn zobrist_hash(state: u64) -> u64 {
+    (0..COLS).fold(0, |hash, col| {
+        hash ^ ZOBRIST_KEYS[col as usize][((state >> col_shift!(col)) & COL_MASK) as usize]
+    })
+}
+
+const fn zobrist_keys() -> [[u64; 1 << COL_BITS]; COLS as usize] {
+    let mut keys = [[0; 1 << COL_BITS]; COLS as usize];
+    let mut seed = ZOBRIST_SEED;
+    let mut col = 0;
+
+    while col < COLS as usize {
+        let mut bit = 0;
+
+        while bit < COL_BITS {
+            // xorshift, the keys only have to look random and stay the same between builds
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+
+            let mut bits = 0;
+            while bits < 1 << COL_BITS {
+                if (bits >> bit) & 1 != 0 {
+                    keys[col][bits] ^= seed;
+                }
+                bits += 1;
+            }
+
+            bit += 1;
+        }
+
+        col += 1;
+    }
+
+    keys
+}
+
+#[allow(clippy::too_many_arguments)]
+fn cache_get(
+    state: u64,
+    moves_made: i8,
+    cache_index: usize,
+    beg_game_cache: &Arc<DashMap<u64, i8>>,
+    end_game_cache: &[AtomicU64],
+    end_game_deep_cache: &[AtomicU64],
+    tablebase: &DashMap<u64, i8>,
+    tablebase_min_moves: i8,
+    beg_game_depth: i8,
+    cache_counters: &CacheCounters,
+    default_bound: i8
+) -> i8 {
+    if moves_made <= beg_game_depth {
         if let Some(cache_bound) = beg_game_cache.get(&state) {
-            return cache_bound.value().clone()
+            cache_counters.add(CacheCounter::BegGameHit);
+            return *cache_bound.value()
         }
     } else {
-        let cache_entry = end_game_cache[cache_index];
+        // an exact eval is both a lower and an upper bound
+        if moves_made >= tablebase_min_moves && let Some(eval) = tablebase.get(&state) {
+            cache_counters.add(CacheCounter::TablebaseHit);
+            return *eval.value()
+        }
+
+        let deep_cache_entry = end_game_deep_cache[cache_index].load(Ordering::Relaxed);
+
+        if get_cache_entry_state!(deep_cache_entry) == state {
+            cache_counters.add(CacheCounter::EndGameHit);
+            return get_cache_entry_eval!(deep_cache_entry)
+        }
+
+        let cache_entry = end_game_cache[cache_index].load(Ordering::Relaxed);
 
         if get_cache_entry_state!(cache_entry) == state {
+            cache_counters.add(CacheCounter::EndGameHit);
             return get_cache_entry_eval!(cache_entry)
         }
     }
 
+    cache_counters.add(CacheCounter::Miss);
     default_bound
 }
 
-fn cache_put(bound: i8, state: u64, moves_made: i8, cache_index: usize, beg_game_cache: &Arc<DashMap<u64, i8>>, end_game_cache: &mut Vec<u64>, cmp: fn(i8, i8) -> i8) {
-    if moves_made > BEGINNING_GAME_CACHE_DEPTH {
-        end_game_cache[cache_index] = create_cache_entry!(state, bound);
+// the deep end game slot keeps whichever entry took more nodes to search, anything it rejects goes to the
+// always-replace slot so recent shallow results are still cached
+#[allow(clippy::too_many_arguments)]
+fn cache_put(
+    bound: i8,
+    state: u64,
+    moves_made: i8,
+    cache_index: usize,
+    work: usize,
+    beg_game_cache: &Arc<DashMap<u64, i8>>,
+    end_game_cache: &[AtomicU64],
+    end_game_deep_cache: &[AtomicU64],
+    end_game_work: &[AtomicU8],
+    beg_game_capacity: Option<usize>,
+    beg_game_eviction_mark: &AtomicUsize,
+    beg_game_depth: i8,
+    cache_counters: &CacheCounters,
+    cmp: fn(i8, i8) -> i8
+) {
+    if moves_made > beg_game_depth {
+        let work = (usize::BITS - work.leading_zeros()) as u8;
+
+        let deep_cache_entry = end_game_deep_cache[cache_index].load(Ordering::Relaxed);
+        let collision = deep_cache_entry != 0 && get_cache_entry_state!(deep_cache_entry) != state;
+
+        if collision {
+            cache_counters.add(CacheCounter::EndGameCollision);
+        }
+
+        if work >= end_game_work[cache_index].load(Ordering::Relaxed) || get_cache_entry_state!(deep_cache_entry) == state {
+            if collision {
+                cache_counters.add(CacheCounter::EndGameOverwrite);
+            }
+
+            end_game_deep_cache[cache_index].store(create_cache_entry!(state, bound), Ordering::Relaxed);
+            end_game_work[cache_index].store(work, Ordering::Relaxed);
+        } else {
+            // an extra atomic load, so only made when it's counted
+            #[cfg(feature = "stats")]
+            {
+                let cache_entry = end_game_cache[cache_index].load(Ordering::Relaxed);
+
+                if cache_entry != 0 && get_cache_entry_state!(cache_entry) != state {
+                    cache_counters.add(CacheCounter::EndGameOverwrite);
+                }
+            }
+
+            end_game_cache[cache_index].store(create_cache_entry!(state, bound), Ordering::Relaxed);
+        }
     } else {
         beg_game_cache.entry(state)
             .and_modify(|entry| *entry = cmp(*entry, bound))
             .or_insert(bound);
+
+        if let Some(capacity) = beg_game_capacity {
+            evict_over_capacity(beg_game_cache, capacity, beg_game_eviction_mark);
+        }
+    }
+}
+
+impl CacheCounter {
+    #[cfg(feature = "stats")]
+    const COUNT: usize = CacheCounter::EndGameOverwrite as usize + 1;
+}
+
+// every method compiles to nothing without the stats feature, so the search pays for counters only when asked
+impl CacheCounters {
+    #[inline(always)]
+    fn add(&self, _counter: CacheCounter) {
+        #[cfg(feature = "stats")]
+        self.counts[_counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        #[cfg(feature = "stats")]
+        self.counts.iter().for_each(|count| count.store(0, Ordering::Relaxed));
+    }
+
+    fn copy(&self) -> Self {
+        Self {
+            #[cfg(feature = "stats")]
+            counts: self.counts.each_ref().map(|count| AtomicU64::new(count.load(Ordering::Relaxed))),
+        }
+    }
+}
+
+// sweeps the cache once it's past both capacity and the mark, returning whether it did. The mark is then set a
+// third above what the sweep left, which is under capacity unless the shallowest layer kept the sweep from
+// reaching its target; a cache that can't shrink is then swept again only after it grows by that third, rather
+// than on every put
+fn evict_over_capacity(beg_game_cache: &DashMap<u64, i8>, capacity: usize, eviction_mark: &AtomicUsize) -> bool {
+    if beg_game_cache.len() <= capacity.max(eviction_mark.load(Ordering::Relaxed)) {
+        return false;
+    }
+
+    evict_deepest(beg_game_cache, capacity * BEG_GAME_EVICTION_NUMERATOR / BEG_GAME_EVICTION_DENOMINATOR);
+    eviction_mark.store(
+        beg_game_cache.len() * BEG_GAME_EVICTION_DENOMINATOR / BEG_GAME_EVICTION_NUMERATOR,
+        Ordering::Relaxed
+    );
+
+    true
+}
+
+// drops whole layers of positions, the most moves made first, until at most target entries are left; deep
+// positions are the cheapest to search again, and the shallow ones include any database entries. The shallowest
+// layer is always kept, even when it's over target on its own, so the root's neighbourhood is never thrown away
+fn evict_deepest(beg_game_cache: &DashMap<u64, i8>, target: usize) {
+    let mut layer_sizes = [0; MAX_TOTAL_MOVES as usize + 1];
+
+    for entry in beg_game_cache.iter() {
+        layer_sizes[bitboard_moves_made(*entry.key()) as usize] += 1;
+    }
+
+    let shallowest_layer = layer_sizes.iter().position(|&layer_size| layer_size != 0).unwrap_or(0);
+    let mut kept = 0;
+    let kept_layers = layer_sizes.iter()
+        .take_while(|&&layer_size| {
+            kept += layer_size;
+            kept <= target
+        })
+        .count()
+        .max(shallowest_layer + 1);
+
+    beg_game_cache.retain(|&state, _| (bitboard_moves_made(state) as usize) < kept_layers);
+}
+
+// every column's sentinel sits on top of its pieces; a corrupt key loaded from a database may lack one, which
+// counts as an empty column rather than panicking
+fn bitboard_moves_made(state: u64) -> i8 {
+    (0..COLS).map(|col| ((state >> col_shift!(col)) & COL_MASK).checked_ilog2().unwrap_or(0) as i8).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every position from the start with moves_made in layers, each worth its own moves made
+    fn beg_game_cache(layers: core::ops::Range<usize>) -> DashMap<u64, i8> {
+        let cache = DashMap::new();
+
+        for state in State::start_state().generate_states(layers.end - 1) {
+            let moves_made = bitboard_moves_made(state);
+
+            if layers.contains(&(moves_made as usize)) {
+                cache.insert(state, moves_made);
+            }
+        }
+
+        cache
+    }
+
+    fn xorshift(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    fn layer_size(cache: &DashMap<u64, i8>, moves_made: i8) -> usize {
+        cache.iter().filter(|entry| *entry.value() == moves_made).count()
+    }
+
+    #[test]
+    fn eviction_drops_the_deepest_layers_that_do_not_fit() {
+        let cache = beg_game_cache(0..4);
+        let (layer_0, layer_1, layer_2) = (layer_size(&cache, 0), layer_size(&cache, 1), layer_size(&cache, 2));
+
+        evict_deepest(&cache, layer_0 + layer_1 + layer_2);
+
+        assert_eq!(cache.len(), layer_0 + layer_1 + layer_2);
+        assert_eq!(layer_size(&cache, 3), 0);
+    }
+
+    #[test]
+    fn eviction_keeps_the_shallowest_layer_even_over_target() {
+        let cache = beg_game_cache(2..4);
+        let layer_2 = layer_size(&cache, 2);
+
+        evict_deepest(&cache, layer_2 / 2);
+
+        assert_eq!(cache.len(), layer_2);
+        assert!(cache.iter().all(|entry| *entry.value() == 2));
+    }
+
+    // layer 3 is the shallowest, so no sweep can bring it under capacity; each one lets it grow by a third before
+    // the next, rather than every put past capacity scanning it again
+    #[test]
+    fn a_cache_stuck_over_capacity_is_swept_once_per_third_it_grows() {
+        let layer_3 = beg_game_cache(3..4);
+        let (cache, eviction_mark, capacity) = (DashMap::new(), AtomicUsize::new(0), 8);
+        let mut sweeps = 0;
+
+        for entry in layer_3.iter() {
+            cache.insert(*entry.key(), *entry.value());
+            sweeps += evict_over_capacity(&cache, capacity, &eviction_mark) as usize;
+        }
+
+        let mut expected_sweeps = 0;
+        let mut swept_at = capacity + 1;
+
+        while swept_at <= layer_3.len() {
+            expected_sweeps += 1;
+            swept_at = swept_at * BEG_GAME_EVICTION_DENOMINATOR / BEG_GAME_EVICTION_NUMERATOR + 1;
+        }
+
+        assert_eq!(cache.len(), layer_3.len());
+        assert_eq!(sweeps, expected_sweeps);
+        assert!(sweeps * 10 < layer_3.len() - capacity);
+    }
+
+    #[test]
+    fn a_bounded_cache_keeps_its_shallowest_positions_however_small() {
+        let mut caches = StateCaches::new().with_beg_game_capacity(Some(0));
+
+        for state in [State::start_state(), State::from_moves(&[3]).unwrap(), State::from_moves(&[2]).unwrap()] {
+            let state_bitboard = state.to_bitboard();
+            caches.put_lower_bound(0, state_bitboard, state.moves_made, caches.cache_index(state_bitboard), 0);
+        }
+
+        assert_eq!(caches.beg_game_lower_bound_cache.len(), 1);
+        assert!(caches.beg_game_lower_bound_cache.contains_key(&State::start_state().to_bitboard()));
+    }
+
+    // the parent peeks at a child's upper bound under state_bitboard(opp_pieces, the child's height map) before
+    // the child is ever searched, so that key has to be the one the child puts its bounds under, mirrored or not
+    #[test]
+    fn a_parent_finds_the_bounds_its_children_put() {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+
+        for zobrist_index in [false, true] {
+            for _ in 0..20 {
+                let mut state = State::start_state();
+
+                while !state.is_terminal() {
+                    for col in state.legal_moves() {
+                        let child = state.play_move(col);
+                        let peek_key = state_bitboard(state.opp_pieces, child.height_map);
+                        let mut caches = StateCaches::with_capacity((1 << 10) + 1).with_zobrist_index(zobrist_index);
+
+                        assert_eq!(peek_key, child.to_bitboard());
+                        assert_eq!(peek_key, child.mirror().to_bitboard());
+
+                        let child_key = child.mirror().to_bitboard();
+                        caches.put_upper_bound(3, child_key, child.moves_made, caches.cache_index(child_key), 1 << 20);
+
+                        assert_eq!(caches.get_upper_bound(peek_key, child.moves_made, caches.cache_index(peek_key)), 3);
+                    }
+
+                    let legal_moves = state.legal_moves();
+                    state = state.play_move(legal_moves[xorshift(&mut seed) as usize % legal_moves.len()]);
+                }
+            }
+        }
     }
 }
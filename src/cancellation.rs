@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+
+
+// a clonable handle to one cancelled flag; clones share the flag, while a child has a flag of its own that its
+// parent's cancel also sets, so cancelling one token stops every worker searching under it and its children
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenInner>,
+}
+
+#[derive(Default)]
+struct TokenInner {
+    cancelled: AtomicBool,
+    // weak so a long lived parent doesn't keep every child it ever made alive, the dead ones are pruned
+    // whenever a child is added
+    children: Mutex<Vec<Weak<TokenInner>>>,
+}
+
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Relaxed)
+    }
+
+    // cancelled along with self, or on its own without touching self; a child of a cancelled token starts out
+    // cancelled
+    pub fn child(&self) -> Self {
+        let child = Self::new();
+        let mut children = self.inner.children.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // checked under the lock cancel holds while it sets the flag, so a child is never missed
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.inner));
+        }
+
+        child
+    }
+
+    // the flag the search polls, a plain load per node like any other terminate flag
+    pub(crate) fn flag(&self) -> &AtomicBool {
+        &self.inner.cancelled
+    }
+}
+
+impl TokenInner {
+    fn cancel(&self) {
+        let children = {
+            let mut children = self.children.lock().unwrap_or_else(PoisonError::into_inner);
+            self.cancelled.store(true, Ordering::Relaxed);
+
+            std::mem::take(&mut *children)
+        };
+
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
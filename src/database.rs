@@ -1,95 +1,426 @@
 use crate::caches::StateCaches;
-use crate::caches::CACHE_VALUE_SHIFT;
+use crate::cancellation::CancellationToken;
 use crate::engine::optimal_moves;
-use crate::engine::{MAX_PLAYER_MOVES};
-use crate::error::Result;
-use crate::state::{State, BOARD_MASK};
-use crate::worker_threads::{spawn_database_generator_worker_threads, WorkerThreadHandler};
+use crate::engine::{evaluate_position_rec, is_win, CACHE_VALUE_SHIFT, FULL_DEPTH, MAX_EVAL, MAX_PLAYER_MOVES, MIN_EVAL, WIN_LENGTH};
+use crate::error::{Connect4Error, Result};
+use crate::state::{State, BOARD_MASK, COLS, ROWS};
+use crate::worker_threads::{spawn_database_generator_worker_threads, worker_thread_panic, StateQueue, WorkerThreadHandler};
 use crate::{create_cache_entry, get_cache_entry_eval, get_cache_entry_state};
 use dashmap::DashMap;
-use std::collections::{HashSet, VecDeque};
-use std::fs::File;
+use log::debug;
+use std::cmp::{max, min};
+use std::collections::HashSet;
+use std::fs::{self, File};
 use std::io;
-use std::io::{BufWriter, Read, Write};
-use std::sync::{Arc, Mutex};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::iter;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 
 const LOWER_BOUND_DATABASE_NAME: &str = "lower_bound_database.bin";
 const UPPER_BOUND_DATABASE_NAME: &str = "upper_bound_database.bin";
+const TABLEBASE_NAME: &str = "tablebase.bin";
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 64;
+// the fewest entries a reader thread is given, so a file this size or smaller is read on one thread
+const MIN_ENTRIES_PER_READER_THREAD: usize = 1 << 16;
+const DATABASE_MAGIC: [u8; 4] = *b"C4DB";
+const DATABASE_VERSION: u16 = 2;
+// magic, version, rows, cols, cache value shift, max player moves, entry count
+const DATABASE_HEADER_BYTES: usize = 4 + 2 + 1 + 1 + 1 + 1 + 8;
+// everything the entry encoding depends on, an old file written with different constants would decode to wrong evals
+const DATABASE_LAYOUT: [u8; 4] = [ROWS as u8, COLS as u8, CACHE_VALUE_SHIFT, MAX_PLAYER_MOVES as u8];
+
+
+fn invalid_database(filename: &str, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{filename}: {reason}"))
+}
+
+fn write_database_header(writer: &mut impl Write, entry_count: u64) -> io::Result<()> {
+    writer.write_all(&DATABASE_MAGIC)?;
+    writer.write_all(&DATABASE_VERSION.to_le_bytes())?;
+    writer.write_all(&DATABASE_LAYOUT)?;
+    writer.write_all(&entry_count.to_le_bytes())
+}
 
+// returns the number of entries that follow the header
+fn read_database_header(reader: &mut impl Read, filename: &str) -> Result<u64> {
+    let mut header = [0; DATABASE_HEADER_BYTES];
 
-fn read_database_from_file(filename: &str, cache: Arc<DashMap<u64, i8>>) -> io::Result<()> {
-    let mut file = File::open(filename)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
+    reader.read_exact(&mut header).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_database(filename, "truncated header"),
+        _ => err,
+    })?;
 
-    let entries: Vec<u64> = buffer
-        .chunks_exact(8)
-        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
-        .collect();
+    let (magic, header) = header.split_at(DATABASE_MAGIC.len());
+    let (version, header) = header.split_at(2);
+    let (layout, entry_count) = header.split_at(DATABASE_LAYOUT.len());
 
-    for entry in entries {
-        let state = get_cache_entry_state!(entry);
-        let eval = get_cache_entry_eval!(entry);
+    if magic != DATABASE_MAGIC {
+        return Err(invalid_database(filename, "not a database file").into());
+    }
+
+    let version = u16::from_le_bytes(version.try_into().unwrap());
+    if version != DATABASE_VERSION {
+        return Err(Connect4Error::DatabaseVersionMismatch(
+            format!("{filename} has format version {version}, expected {DATABASE_VERSION}")));
+    }
+
+    if layout != DATABASE_LAYOUT {
+        return Err(Connect4Error::DatabaseVersionMismatch(
+            format!("{filename} was written with layout {layout:?}, expected {DATABASE_LAYOUT:?}")));
+    }
+
+    Ok(u64::from_le_bytes(entry_count.try_into().unwrap()))
+}
+
+// checks the header and that its entry count fills the rest of the file, returning the reader positioned at the
+// first entry and the count
+fn open_database(file: File, filename: &str) -> Result<(BufReader<File>, u64)> {
+    let file_size = file.metadata()?.len().saturating_sub(DATABASE_HEADER_BYTES as u64);
+    let mut reader = BufReader::new(file);
+    let entry_count = read_database_header(&mut reader, filename)?;
+
+    // a corrupt count times the entry size can overflow, and wrapping around could make it match
+    if entry_count.checked_mul(8) != Some(file_size) {
+        return Err(invalid_database(filename, "entry count does not match the file size").into());
+    }
+
+    Ok((reader, entry_count))
+}
+
+// the raw entries of a database file, after checking its header; read into a buffer sized from the file up
+// front, so a large file isn't copied again and again as the buffer grows
+fn read_database_bytes(file: File, filename: &str) -> Result<Vec<u8>> {
+    let (mut reader, entry_count) = open_database(file, filename)?;
+
+    let mut buffer = Vec::with_capacity(usize::try_from(entry_count * 8).unwrap_or(0));
+    reader.read_to_end(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+// the entries of a database file one at a time, for going through files too large to load; a missing file has
+// none, the same as for read_database_from_file
+struct DatabaseEntries {
+    reader: Option<BufReader<File>>,
+    remaining: u64,
+    last_state: Option<u64>,
+    filename: String,
+}
+
+impl DatabaseEntries {
+    fn open(path: &Path) -> Result<Self> {
+        let filename = path.display().to_string();
+        let (reader, remaining) = match File::open(path) {
+            Ok(file) => open_database(file, &filename).map(|(reader, remaining)| (Some(reader), remaining))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (None, 0),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self { reader, remaining, last_state: None, filename })
+    }
+
+    // an entry whose state isn't above the one before is an error, streaming relies on the order write_cache_to_file
+    // writes them in
+    fn next_entry(&mut self) -> Result<Option<(u64, i8)>> {
+        let Some(reader) = self.reader.as_mut().filter(|_| self.remaining > 0) else {
+            return Ok(None);
+        };
+
+        let mut entry = [0; 8];
+        reader.read_exact(&mut entry)?;
+        self.remaining -= 1;
+
+        let (state, bound) = decode_database_entry(&entry, &self.filename)?;
+
+        if self.last_state.is_some_and(|last_state| state <= last_state) {
+            return Err(invalid_database(&self.filename, "entries not sorted by state").into());
+        }
 
-        cache.insert(state, eval);
+        self.last_state = Some(state);
+
+        Ok(Some((state, bound)))
+    }
+}
+
+fn decode_database_entry(entry: &[u8], filename: &str) -> Result<(u64, i8)> {
+    // a corrupt value byte would overflow get_cache_entry_eval!, so it's checked against the evals
+    // create_cache_entry! can store first
+    let stored_evals = (MIN_EVAL + MAX_PLAYER_MOVES) as u64..=(MAX_EVAL + MAX_PLAYER_MOVES) as u64;
+    let entry = u64::from_le_bytes(entry.try_into().unwrap());
+
+    if !stored_evals.contains(&(entry >> CACHE_VALUE_SHIFT)) {
+        return Err(invalid_database(filename, "entry eval out of range").into());
+    }
+
+    Ok((get_cache_entry_state!(entry), get_cache_entry_eval!(entry)))
+}
+
+// every (state, bound) entry of a database file, after checking its header
+fn read_database_entries(file: File, filename: &str) -> Result<Vec<(u64, i8)>> {
+    read_database_bytes(file, filename)?.chunks_exact(8)
+        .map(|entry| decode_database_entry(entry, filename))
+        .collect()
+}
+
+// a missing database just means nothing has been generated yet, so the cache is left empty
+fn read_database_from_file(path: &Path, cache: Arc<DashMap<u64, i8>>) -> Result<()> {
+    read_database_from_file_with(path, cache, |_, bound| bound)
+}
+
+// cmp picks what to keep when the cache already holds a bound for the same state; the entries are decoded and
+// inserted by one thread per core, each taking its own slice of the file
+fn read_database_from_file_with(path: &Path, cache: Arc<DashMap<u64, i8>>, cmp: fn(i8, i8) -> i8) -> Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let filename = path.display().to_string();
+    let buffer = read_database_bytes(file, &filename)?;
+
+    let num_threads = thread::available_parallelism().map_or(1, |parallelism| parallelism.get());
+    let entries_per_thread = (buffer.len() >> 3).div_ceil(num_threads).max(MIN_ENTRIES_PER_READER_THREAD);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = buffer.chunks(entries_per_thread << 3).map(|entries| scope.spawn(|| -> Result<()> {
+            for entry in entries.chunks_exact(8) {
+                let (state, eval) = decode_database_entry(entry, &filename)?;
+
+                cache.entry(state)
+                    .and_modify(|entry| *entry = cmp(*entry, eval))
+                    .or_insert(eval);
+            }
+
+            Ok(())
+        })).collect();
+
+        handles.into_iter().try_for_each(|handle| handle.join().map_err(worker_thread_panic)?)
+    })
+}
+
+// one "position,eval" row per entry, with the position in State::to_notation form
+pub fn export_database_csv(bin_path: &Path, csv_path: &Path) -> Result<()> {
+    let filename = bin_path.display().to_string();
+    let entries = read_database_entries(File::open(bin_path)?, &filename)?;
+
+    let mut writer = BufWriter::new(File::create(csv_path)?);
+    writeln!(writer, "position,eval")?;
+
+    for (state, eval) in entries {
+        writeln!(writer, "{},{eval}", State::try_from_bitboard(state)?.to_notation())?;
     }
 
+    writer.flush()?;
+
     Ok(())
 }
 
-fn read_databases_into_caches(caches: &StateCaches) -> io::Result<()> {
-    read_database_from_file(LOWER_BOUND_DATABASE_NAME, caches.beg_game_lower_bound_cache.clone())?;
-    read_database_from_file(UPPER_BOUND_DATABASE_NAME, caches.beg_game_upper_bound_cache.clone())?;
+pub fn read_databases_into_caches(caches: &mut StateCaches) -> Result<()> {
+    read_databases_in(Path::new(""), caches)
+}
+
+// dir is where a build keeps its databases, the empty path for the working directory; any that are missing
+// are left out of the caches
+fn read_databases_in(dir: &Path, caches: &mut StateCaches) -> Result<()> {
+    read_database_from_file(&dir.join(LOWER_BOUND_DATABASE_NAME), caches.beg_game_lower_bound_cache.clone())?;
+    read_database_from_file(&dir.join(UPPER_BOUND_DATABASE_NAME), caches.beg_game_upper_bound_cache.clone())?;
+    read_tablebase_from_file(&dir.join(TABLEBASE_NAME), caches)?;
 
     Ok(())
 }
 
-fn write_cache_to_file(filename: &str, cache: Arc<DashMap<u64, i8>>) -> io::Result<()> {
-    let mut database_entries = Vec::with_capacity(cache.len());
+// the search only looks positions up in the tablebase from the fewest moves made of any entry onwards
+fn read_tablebase_from_file(path: &Path, caches: &mut StateCaches) -> Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
 
-    for entry in cache.iter() {
-        let (state, bound) = entry.pair().clone();
-        database_entries.push(create_cache_entry!(state, bound));
+    for (state, eval) in read_database_entries(file, &path.display().to_string())? {
+        caches.put_tablebase_eval(eval, state, State::try_from_bitboard(state)?.moves_made);
     }
 
-    let mut bytes = Vec::with_capacity(database_entries.len() << 3);
+    Ok(())
+}
+
+// entries are written sorted by state, so the same entries always make the same file whatever order the map
+// holds them in, and merge_databases can stream them
+fn write_cache_to_file(path: &Path, cache: Arc<DashMap<u64, i8>>) -> io::Result<()> {
+    let mut entries: Vec<u64> = cache.iter().map(|entry| create_cache_entry!(*entry.key(), *entry.value())).collect();
+    entries.sort_unstable_by_key(|&entry| get_cache_entry_state!(entry));
+
+    write_database_file(path, entries.into_iter().map(Ok))
+}
+
+// the file is written beside path and renamed over it, so a build killed mid-write leaves the old one; the
+// header's entry count is filled in once every entry is written, so they can come from an iterator of any length
+fn write_database_file<E: From<io::Error>>(
+    path: &Path,
+    entries: impl Iterator<Item = std::result::Result<u64, E>>,
+) -> std::result::Result<(), E> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    write_database_header(&mut writer, 0)?;
 
-    for &entry in &database_entries {
-        bytes.extend_from_slice(&entry.to_le_bytes());
+    let mut entry_count = 0;
+
+    for entry in entries {
+        writer.write_all(&entry?.to_le_bytes())?;
+        entry_count += 1;
     }
 
-    let mut writer = BufWriter::new(File::create(filename)?);
-    writer.write_all(&bytes)?;
-    writer.flush()?;
+    let mut file = writer.into_inner().map_err(io::IntoInnerError::into_error)?;
+    file.seek(SeekFrom::Start(0))?;
+    write_database_header(&mut file, entry_count)?;
+    file.sync_all()?;
+
+    Ok(fs::rename(temp_path, path)?)
+}
+
+fn write_caches_to_databases(dir: &Path, caches: &StateCaches) -> io::Result<()> {
+
+    write_cache_to_file(&dir.join(LOWER_BOUND_DATABASE_NAME), caches.beg_game_lower_bound_cache.clone())?;
+    write_cache_to_file(&dir.join(UPPER_BOUND_DATABASE_NAME), caches.beg_game_upper_bound_cache.clone())?;
 
     Ok(())
 }
 
-fn write_caches_to_databases(caches: StateCaches) -> io::Result<()> {
+// inputs and output are directories holding a lower and an upper bound database each, as written by a build;
+// where inputs disagree on a state the tightest bound wins, the same as when the search stores bounds. The
+// inputs' entries are sorted by state, so they're merged a state at a time with one entry of each in memory
+pub fn merge_databases(inputs: &[&Path], output: &Path) -> Result<()> {
+    for (database_name, cmp) in [
+        (LOWER_BOUND_DATABASE_NAME, max as fn(i8, i8) -> i8),
+        (UPPER_BOUND_DATABASE_NAME, min as fn(i8, i8) -> i8),
+    ] {
+        let mut readers = inputs.iter()
+            .map(|input| DatabaseEntries::open(&input.join(database_name)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut heads = readers.iter_mut().map(DatabaseEntries::next_entry).collect::<Result<Vec<_>>>()?;
+
+        let merged = iter::from_fn(|| {
+            let state = heads.iter().flatten().map(|&(state, _)| state).min()?;
+            let mut merged_bound = None;
+
+            for (head, reader) in heads.iter_mut().zip(&mut readers) {
+                if let Some((head_state, bound)) = *head && head_state == state {
+                    merged_bound = Some(merged_bound.map_or(bound, |merged_bound| cmp(merged_bound, bound)));
+
+                    match reader.next_entry() {
+                        Ok(next) => *head = next,
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+            }
 
-    write_cache_to_file(LOWER_BOUND_DATABASE_NAME, caches.beg_game_lower_bound_cache)?;
-    write_cache_to_file(UPPER_BOUND_DATABASE_NAME, caches.beg_game_upper_bound_cache)?;
+            merged_bound.map(|bound| Ok(create_cache_entry!(state, bound)))
+        });
+
+        write_database_file(&output.join(database_name), merged)?;
+    }
 
     Ok(())
 }
 
+pub fn generate_tablebase(min_moves: i8) -> Result<usize> {
+    generate_tablebase_from(&State::start_state(), min_moves)
+}
+
+// solves the positions min_moves moves into the game reachable from root and writes them to the tablebase;
+// returns the positions searched. Only that frontier is walked, the searches from it cover every deeper position
+// in the end game caches as they go, so the walk costs the same as generate_states to that depth
+pub fn generate_tablebase_from(root: &State, min_moves: i8) -> Result<usize> {
+    let mut caches = StateCaches::new();
+    let pos = solve_tablebase_from(root, min_moves, &mut caches)?;
+
+    debug!("Tablebase Entries: {}", caches.tablebase.len());
+    write_cache_to_file(Path::new(TABLEBASE_NAME), caches.tablebase.clone())?;
+
+    Ok(pos)
+}
+
+// the frontier is solved as it's generated rather than collected first; a root already past min_moves is the
+// whole frontier
+fn solve_tablebase_from(root: &State, min_moves: i8, caches: &mut StateCaches) -> Result<usize> {
+    let frontier_moves = max(root.moves_made, min_moves);
+    let mut pos = 0;
+
+    let frontier = root.states_iter((frontier_moves - root.moves_made) as usize)
+        .map(State::from_bitboard)
+        .filter(|state| state.moves_made == frontier_moves)
+        .filter(|state| !state.is_full() && !is_win(state.opp_pieces, WIN_LENGTH));
+
+    for state in frontier {
+        let eval = evaluate_position_rec(
+            state.curr_pieces,
+            state.opp_pieces,
+            state.height_map,
+            state.moves_made,
+            MIN_EVAL,
+            MAX_EVAL,
+            FULL_DEPTH,
+            caches,
+            &AtomicBool::new(false),
+            &mut false,
+            &mut pos,
+        ).ok_or(Connect4Error::EvaluatePositionError)?;
+
+        caches.put_tablebase_eval(eval, state.to_bitboard(), state.moves_made);
+    }
+
+    Ok(pos)
+}
+
+// the positions depth moves on from start where every other move is an optimal one, each only once however
+// many move orders or mirror images reach it
+fn database_frontier(start: State, depth: usize, caches: &mut StateCaches) -> Result<Vec<State>> {
+    let mut seen = HashSet::new();
+    let mut possible_states: Vec<State> = Vec::new();
+
+    if (depth & 1) == 0 {
+        generate_optimal_reachable_states(start, caches, depth >> 1, &mut seen, &mut possible_states)?;
+    } else {
+        for next_state in start.next_states() {
+            generate_optimal_reachable_states(next_state, caches, depth >> 1, &mut seen, &mut possible_states)?;
+        }
+    }
+
+    // seen holds canonical bitboards and is checked before a state is pushed, so a mirror image or a
+    // transposition of a frontier state never costs a second solve
+    debug_assert_eq!(
+        possible_states.iter().map(State::to_bitboard).collect::<HashSet<_>>().len(),
+        possible_states.len(),
+        "duplicate states in the database frontier"
+    );
+
+    Ok(possible_states)
+}
+
 fn generate_optimal_reachable_states(
     state: State,
     caches: &mut StateCaches,
     depth: usize,
     seen: &mut HashSet<u64>,
-    possible_states: &mut VecDeque<State>,
+    possible_states: &mut Vec<State>,
 ) -> Result<()> {
     let state_bitboard = state.to_bitboard();
 
     if seen.insert(state_bitboard) {
         if depth == 0 {
-            possible_states.push_back(state);
+            possible_states.push(state);
             return Ok(());
         }
 
-        let (_, best_moves) = optimal_moves(&state, caches, &mut 0)?;
+        let best_moves = optimal_moves(&state, caches)?.best_moves;
 
         for best_move in best_moves {
             for next_state in state.play_move(best_move).next_states() {
@@ -101,10 +432,50 @@ fn generate_optimal_reachable_states(
     Ok(())
 }
 
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+// returns the handler SIGINT had before, for restore_interrupt_handler to put back so a caller's own handler
+// works again once the build is over; None if this one couldn't be installed and there's nothing to put back
+fn install_interrupt_handler() -> Option<libc::sighandler_t> {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+
+    let previous_handler = unsafe {
+        libc::signal(libc::SIGINT, handle_interrupt as *const () as libc::sighandler_t)
+    };
+
+    (previous_handler != libc::SIG_ERR).then_some(previous_handler)
+}
+
+fn restore_interrupt_handler(previous_handler: Option<libc::sighandler_t>) {
+    if let Some(previous_handler) = previous_handler {
+        unsafe {
+            libc::signal(libc::SIGINT, previous_handler);
+        }
+    }
+}
+
 pub fn generate_database(depth: usize, num_workers: usize) -> Result<usize> {
-    let mut caches = StateCaches::new();
-    read_databases_into_caches(&caches)?;
+    generate_database_with_checkpoints(depth, num_workers, DEFAULT_CHECKPOINT_INTERVAL)
+}
 
+// the databases are flushed to disk every checkpoint_interval solved states (0 only writes them at the end),
+// and a rerun loads them and only solves the states they don't, so resuming or extending a build is cheap
+pub fn generate_database_with_checkpoints(depth: usize, num_workers: usize, checkpoint_interval: usize) -> Result<usize> {
+    generate_database_into(Path::new(""), depth, num_workers, checkpoint_interval)
+}
+
+// generate_database_with_checkpoints with the databases read from and written to dir instead of the working
+// directory; dir has to exist
+pub fn generate_database_into(
+    dir: &Path,
+    depth: usize,
+    num_workers: usize,
+    checkpoint_interval: usize,
+) -> Result<usize> {
     let start = State::start_state();
 
     // let board = vec![
@@ -119,30 +490,388 @@ pub fn generate_database(depth: usize, num_workers: usize) -> Result<usize> {
     //
     // start = State::encode(board);
 
-    let mut seen = HashSet::new();
-    let mut possible_states: VecDeque<State> = VecDeque::new();
+    generate_database_in(dir, start, depth, num_workers, checkpoint_interval)
+}
 
-    if (depth & 1) == 0 {
-        generate_optimal_reachable_states(start, &mut caches, depth >> 1, &mut seen, &mut possible_states)?;
-    } else {
-        for next_state in start.next_states() {
-            generate_optimal_reachable_states(next_state, &mut caches, depth >> 1, &mut seen, &mut possible_states)?;
-        }
-    }
+// the databases in dir for the positions depth moves on from start
+fn generate_database_in(
+    dir: &Path,
+    start: State,
+    depth: usize,
+    num_workers: usize,
+    checkpoint_interval: usize,
+) -> Result<usize> {
+    let mut caches = StateCaches::new();
+    read_databases_in(dir, &mut caches)?;
+
+    let mut possible_states = database_frontier(start, depth, &mut caches)?;
+
+    debug!("Possible States: {}", possible_states.len());
+
+    // states solved by an earlier build, whether it finished or was interrupted, already have an exact eval
+    possible_states.retain(|state| caches.get_beg_game_eval(state.to_bitboard()).is_none());
 
-    println!("Possible States: {}", possible_states.len());
+    debug!("Unsolved States: {}", possible_states.len());
 
-    let states = Arc::new(Mutex::new(possible_states));
+    let previous_handler = install_interrupt_handler();
+
+    let states = Arc::new(StateQueue::new(possible_states));
+    let cancel = CancellationToken::new();
     let worker_handlers: Vec<WorkerThreadHandler> = spawn_database_generator_worker_threads(
-        num_workers, states, &caches);
+        num_workers, states.clone(), &caches, &cancel);
+    let mut checkpointed = 0;
+    let mut checkpoint_result = Ok(());
 
-    let mut pos = 0;
+    while !worker_handlers.iter().all(WorkerThreadHandler::is_finished) {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            cancel.cancel();
+        }
+
+        let completed = states.completed();
+        if checkpoint_result.is_ok() && checkpoint_interval > 0 && completed >= checkpointed + checkpoint_interval {
+            debug!("Checkpoint: {completed} / {} States", states.len());
+            checkpoint_result = write_caches_to_databases(dir, &caches);
+            checkpointed = completed;
+
+            // a checkpoint that can't be written ends the build like an interrupt, the error is returned once the
+            // workers have stopped
+            if checkpoint_result.is_err() {
+                cancel.cancel();
+            }
+        }
+
+        thread::sleep(INTERRUPT_POLL_INTERVAL);
+    }
+
+    // every worker is joined even after one fails, keeping the first error
+    let mut join_result: Result<usize> = Ok(0);
 
     for handler in worker_handlers {
-        pos += handler.join()?;
+        let worker_result = handler.join();
+        join_result = join_result.and_then(|pos| Ok(pos + worker_result?));
     }
 
-    write_caches_to_databases(caches)?;
+    restore_interrupt_handler(previous_handler);
+
+    // partial results are still written when the build was interrupted or failed
+    let write_result = write_caches_to_databases(dir, &caches);
+
+    checkpoint_result?;
+    let pos = join_result?;
+    write_result?;
 
     Ok(pos)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::col_shift;
+    use crate::engine::evaluate_position;
+    use crate::state::COL_MASK;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    // a file of its own per test under the temp dir, removed when the test is done with it
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn with_bytes(name: &str, bytes: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("connect4-{}-{name}.bin", std::process::id()));
+            fs::write(&path, bytes).unwrap();
+
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    // an empty directory of its own under the temp dir, removed with everything in it
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("connect4-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir(&path).unwrap();
+
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // a build installs its own SIGINT handler, so tests that swap the handler take turns
+    static SIGINT_HANDLER: Mutex<()> = Mutex::new(());
+
+    fn header(entry_count: u64) -> Vec<u8> {
+        let mut header = vec![];
+        write_database_header(&mut header, entry_count).unwrap();
+
+        header
+    }
+
+    fn database(entries: &[u64]) -> Vec<u8> {
+        let mut bytes = header(entries.len() as u64);
+        entries.iter().for_each(|entry| bytes.extend(entry.to_le_bytes()));
+
+        bytes
+    }
+
+    fn read(name: &str, bytes: &[u8]) -> Result<Vec<(u64, i8)>> {
+        let file = TempFile::with_bytes(name, bytes);
+
+        read_database_entries(File::open(&file.0).unwrap(), name)
+    }
+
+    fn assert_invalid_data(result: Result<Vec<(u64, i8)>>, reason: &str) {
+        match result {
+            Err(Connect4Error::DatabaseIOError(err)) if err.kind() == io::ErrorKind::InvalidData => {
+                assert!(err.to_string().ends_with(reason), "expected {reason:?}, got {err}");
+            },
+            result => panic!("expected invalid data ({reason}), got {result:?}"),
+        }
+    }
+
+    fn start_entry(eval: i8) -> u64 {
+        create_cache_entry!(State::start_state().to_bitboard(), eval)
+    }
+
+    #[test]
+    fn entries_read_back_as_written() {
+        let cache = Arc::new(DashMap::new());
+        cache.insert(State::start_state().to_bitboard(), 0);
+        cache.insert(State::from_moves(&[3]).unwrap().to_bitboard(), MIN_EVAL);
+        cache.insert(State::from_moves(&[3, 2]).unwrap().to_bitboard(), MAX_EVAL);
+
+        let file = TempFile::with_bytes("round-trip", &[]);
+        write_cache_to_file(&file.0, cache.clone()).unwrap();
+        let read_back = Arc::new(DashMap::new());
+        read_database_from_file(&file.0, read_back.clone()).unwrap();
+
+        assert_eq!(read_back.len(), cache.len());
+        assert!(cache.iter().all(|entry| read_back.get(entry.key()).map(|eval| *eval) == Some(*entry.value())));
+    }
+
+    fn database_dir(name: &str, lower_bounds: &[(u64, i8)], upper_bounds: &[(u64, i8)]) -> TempDir {
+        let dir = TempDir::new(name);
+
+        for (database_name, bounds) in [
+            (LOWER_BOUND_DATABASE_NAME, lower_bounds),
+            (UPPER_BOUND_DATABASE_NAME, upper_bounds),
+        ] {
+            write_cache_to_file(&dir.0.join(database_name), Arc::new(bounds.iter().copied().collect())).unwrap();
+        }
+
+        dir
+    }
+
+    fn read_dir_database(dir: &TempDir, database_name: &str) -> Vec<(u64, i8)> {
+        read_database_entries(File::open(dir.0.join(database_name)).unwrap(), database_name).unwrap()
+    }
+
+    // the third input has no databases at all, which merges like empty ones
+    #[test]
+    fn merged_databases_keep_the_tightest_bounds_in_state_order() {
+        let [start, x3, x3_o2] = [&[][..], &[3], &[3, 2]].map(|moves| State::from_moves(moves).unwrap().to_bitboard());
+        let first = database_dir("merge-first", &[(start, -2), (x3, 1)], &[(start, 5), (x3_o2, 4)]);
+        let second = database_dir("merge-second", &[(start, 0), (x3_o2, -3)], &[(start, 3), (x3, 6)]);
+        let missing = TempDir::new("merge-missing");
+        let output = TempDir::new("merge-output");
+
+        merge_databases(&[&first.0, &second.0, &missing.0], &output.0).unwrap();
+
+        let mut lower_bounds = vec![(start, 0), (x3, 1), (x3_o2, -3)];
+        let mut upper_bounds = vec![(start, 3), (x3, 6), (x3_o2, 4)];
+        lower_bounds.sort_unstable();
+        upper_bounds.sort_unstable();
+
+        assert_eq!(read_dir_database(&output, LOWER_BOUND_DATABASE_NAME), lower_bounds);
+        assert_eq!(read_dir_database(&output, UPPER_BOUND_DATABASE_NAME), upper_bounds);
+    }
+
+    #[test]
+    fn merging_a_database_out_of_state_order_is_invalid() {
+        let [start, x3] = [&[][..], &[3]].map(|moves| State::from_moves(moves).unwrap().to_bitboard());
+        let (first, second) = (start.min(x3), start.max(x3));
+        let input = TempDir::new("merge-unsorted");
+        let output = TempDir::new("merge-unsorted-output");
+
+        let unsorted = database(&[create_cache_entry!(second, 0), create_cache_entry!(first, 0)]);
+        fs::write(input.0.join(LOWER_BOUND_DATABASE_NAME), unsorted).unwrap();
+
+        assert_invalid_data(merge_databases(&[&input.0], &output.0).map(|_| vec![]), "entries not sorted by state");
+    }
+
+    #[test]
+    fn a_truncated_header_is_invalid() {
+        assert_invalid_data(read("empty", &[]), "truncated header");
+        assert_invalid_data(read("short-header", &header(0)[..DATABASE_HEADER_BYTES - 1]), "truncated header");
+    }
+
+    #[test]
+    fn a_corrupt_header_is_rejected() {
+        let mut bad_magic = database(&[start_entry(0)]);
+        bad_magic[0] ^= 0xff;
+        assert_invalid_data(read("bad-magic", &bad_magic), "not a database file");
+
+        let mut bad_version = database(&[start_entry(0)]);
+        bad_version[DATABASE_MAGIC.len()] ^= 0xff;
+        assert!(matches!(read("bad-version", &bad_version), Err(Connect4Error::DatabaseVersionMismatch(_))));
+
+        let mut bad_layout = database(&[start_entry(0)]);
+        bad_layout[DATABASE_MAGIC.len() + 2] ^= 0xff;
+        assert!(matches!(read("bad-layout", &bad_layout), Err(Connect4Error::DatabaseVersionMismatch(_))));
+    }
+
+    #[test]
+    fn truncated_entries_do_not_match_the_count() {
+        let entries = database(&[start_entry(0), start_entry(1)]);
+
+        assert_invalid_data(read("truncated-entries", &entries[..entries.len() - 3]), "does not match the file size");
+        assert_invalid_data(read("missing-entry", &entries[..entries.len() - 8]), "does not match the file size");
+    }
+
+    // (1 << 61) + 1 entries would be 8 bytes again if the size wrapped
+    #[test]
+    fn an_entry_count_too_large_for_any_file_is_invalid() {
+        let mut bytes = header((1 << 61) + 1);
+        bytes.extend(start_entry(0).to_le_bytes());
+
+        assert_invalid_data(read("huge-count", &bytes), "does not match the file size");
+    }
+
+    #[test]
+    fn evals_out_of_range_are_invalid() {
+        let state = State::start_state().to_bitboard();
+
+        for stored_eval in [0, (MAX_EVAL + MAX_PLAYER_MOVES + 1) as u64, 0xff] {
+            let entry = state | (stored_eval << CACHE_VALUE_SHIFT);
+
+            assert_invalid_data(read("bad-eval", &database(&[entry])), "entry eval out of range");
+        }
+    }
+
+    #[test]
+    fn a_tablebase_entry_without_a_height_sentinel_is_an_error() {
+        let state = State::from_moves(&[3, 3, 2]).unwrap().to_bitboard() & !(COL_MASK << col_shift!(3));
+        let file = TempFile::with_bytes("zeroed-column", &database(&[create_cache_entry!(state, 0)]));
+
+        match read_tablebase_from_file(&file.0, &mut StateCaches::new()) {
+            Err(Connect4Error::InvalidBitboard { bitboard, .. }) => assert_eq!(bitboard, state),
+            result => panic!("expected InvalidBitboard, got {result:?}"),
+        }
+    }
+
+    // 40 moves in with 6 columns still open and no winner yet
+    fn near_terminal_root() -> State {
+        State::from_moves(&[
+            3, 1, 6, 6, 5, 5, 6, 6, 6, 4, 5, 1, 2, 6, 1, 5, 3, 3, 1, 0,
+            0, 0, 5, 6, 5, 5, 3, 3, 2, 1, 4, 0, 4, 0, 3, 4, 1, 4, 1, 4,
+        ]).unwrap()
+    }
+
+    #[test]
+    fn tablebase_holds_the_solved_frontier_and_nothing_else() {
+        let root = near_terminal_root();
+        let min_moves = root.moves_made + 2;
+        let mut caches = StateCaches::new();
+
+        solve_tablebase_from(&root, min_moves, &mut caches).unwrap();
+
+        let frontier: HashSet<u64> = root.generate_states(2).into_iter()
+            .map(State::from_bitboard)
+            .filter(|state| state.moves_made == min_moves && !state.is_terminal())
+            .map(|state| state.to_bitboard())
+            .collect();
+
+        assert!(!frontier.is_empty());
+        assert_eq!(caches.tablebase.len(), frontier.len());
+        assert_eq!(caches.tablebase_min_moves, min_moves);
+
+        for entry in caches.tablebase.iter() {
+            assert!(frontier.contains(entry.key()));
+            assert_eq!(*entry.value(), evaluate_position(&State::from_bitboard(*entry.key())).unwrap());
+        }
+    }
+
+    #[test]
+    fn restoring_the_interrupt_handler_puts_back_the_one_before() {
+        let _sigint = SIGINT_HANDLER.lock().unwrap();
+        let original = unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) };
+
+        let previous_handler = install_interrupt_handler();
+        assert_eq!(previous_handler, Some(libc::SIG_IGN));
+        restore_interrupt_handler(previous_handler);
+
+        let restored = unsafe { libc::signal(libc::SIGINT, original) };
+        assert_eq!(restored, libc::SIG_IGN);
+    }
+
+    #[test]
+    fn a_root_past_min_moves_is_the_whole_frontier() {
+        let root = near_terminal_root();
+        let mut caches = StateCaches::new();
+
+        solve_tablebase_from(&root, root.moves_made - 5, &mut caches).unwrap();
+
+        assert_eq!(caches.tablebase.len(), 1);
+        assert_eq!(caches.get_tablebase_eval(root.to_bitboard()), Some(evaluate_position(&root).unwrap()));
+    }
+
+    #[test]
+    fn a_fresh_build_starts_without_any_database_files() {
+        let _sigint = SIGINT_HANDLER.lock().unwrap();
+        let dir = TempDir::new("fresh-build");
+
+        assert!(generate_database_in(&dir.0, near_terminal_root(), 2, 2, 0).is_ok());
+        assert!(dir.0.join(LOWER_BOUND_DATABASE_NAME).exists());
+        assert!(dir.0.join(UPPER_BOUND_DATABASE_NAME).exists());
+    }
+
+    #[test]
+    fn a_second_build_skips_the_states_the_first_solved() {
+        let _sigint = SIGINT_HANDLER.lock().unwrap();
+        let dir = TempDir::new("second-build");
+
+        let first = generate_database_in(&dir.0, near_terminal_root(), 2, 2, 0).unwrap();
+        let second = generate_database_in(&dir.0, near_terminal_root(), 2, 2, 0).unwrap();
+
+        assert!(first > 0);
+        assert_eq!(second, 0);
+    }
+
+    // every first move and its mirror image reach the frontier at the same depth, with nothing to solve between
+    #[test]
+    fn the_frontier_holds_one_of_each_mirrored_pair() {
+        let frontier = database_frontier(State::start_state(), 1, &mut StateCaches::new()).unwrap();
+        let mut frontier: Vec<u64> = frontier.iter().map(State::to_bitboard).collect();
+        let mut first_moves: Vec<u64> = (0..4).map(|col| State::from_moves(&[col]).unwrap().to_bitboard()).collect();
+        frontier.sort_unstable();
+        first_moves.sort_unstable();
+
+        assert_eq!(frontier, first_moves);
+    }
+
+    // a symmetric root has every reply mirrored, and transpositions two moves on besides
+    #[test]
+    fn the_frontier_has_no_two_states_with_the_same_canonical_bitboard() {
+        let root = State::from_moves(&[
+            2, 1, 4, 5, 3, 5, 3, 1, 0, 0, 6, 6, 6, 4, 0, 2, 0, 2, 6, 4, 4, 0, 2, 6, 4, 1, 2, 5,
+        ]).unwrap();
+        let frontier = database_frontier(root.clone(), 3, &mut StateCaches::new()).unwrap();
+        let distinct: HashSet<u64> = frontier.iter().map(State::to_bitboard).collect();
+
+        assert!(root.is_symmetric());
+        assert!(frontier.len() > 1);
+        assert_eq!(distinct.len(), frontier.len());
+    }
+}
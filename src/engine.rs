@@ -1,57 +1,144 @@
-use crate::caches::{StateCaches, CACHE_SIZE};
-use crate::error::{Connect4Error, Result};
 use crate::state::*;
 use crate::threats::*;
-use crate::worker_threads::*;
 use crate::*;
-use std::cmp::{max, min};
-use std::sync::atomic::{AtomicBool, Ordering};
+use core::cmp::{max, min};
 
 
-const CONNECTION_DIRECTIONS: &[i32; 4] = &[1, 7, 8, 9];
-const MAX_TOTAL_MOVES: i8 = 49;
-pub const MAX_PLAYER_MOVES: i8 = 25;
-pub const MAX_EVAL: i8 = 22;
+// vertical, down-right diagonal, horizontal, up-right diagonal
+pub(crate) const CONNECTION_DIRECTIONS: &[usize; 4] = &[1, COL_BITS - 1, COL_BITS, COL_BITS + 1];
+pub const MAX_TOTAL_MOVES: i8 = DEFAULT_BOARD.max_total_moves();
+pub const MAX_PLAYER_MOVES: i8 = DEFAULT_BOARD.max_player_moves();
+pub const WIN_LENGTH: u32 = DEFAULT_BOARD.win_len;
+pub const MAX_EVAL: i8 = DEFAULT_BOARD.max_eval();
 pub const MIN_EVAL: i8 = -MAX_EVAL;
+pub const FULL_DEPTH: i8 = MAX_TOTAL_MOVES;
 const DRAW: i8 = 0;
+// a cache entry is the state's canonical bitboard with the bound above it, offset by MAX_PLAYER_MOVES so it's
+// never negative; the same layout for every cache, in memory and on disk
+pub const CACHE_VALUE_SHIFT: u8 = 56;
+#[allow(clippy::identity_op)]
 pub const DEFAULT_MOVE_ORDER: u32 = (3 << 0) | (2 << 4) | (4 << 8) | (5 << 12) | (1 << 16) | (6 << 20) | (0 << 24);
-pub const IS_LEGAL: u64 = 0b01111111011111110111111101111111011111110111111101111111;
+// the middle column, then outwards alternating left and right, e.g. 3, 2, 4, 1, 5, 0, 6 for 7 columns
+pub const fn center_first_order(cols: u32) -> u32 {
+    let mut move_order = 0;
+    let mut i = 0;
 
+    while i < cols {
+        let offset = i.div_ceil(2);
+        let col = if (i & 1) == 1 { cols / 2 - offset } else { cols / 2 + offset };
 
+        move_order |= col << index!(i);
+        i += 1;
+    }
+
+    move_order
+}
+
+pub const IS_LEGAL: u64 = DEFAULT_BOARD.is_legal_mask();
+const HEURISTIC_THREAT_WEIGHT: i32 = 2;
+const HEURISTIC_CENTRALITY_DIVISOR: i32 = 8;
+
+
+// with a config, the bounds on that board rather than DEFAULT_BOARD
 macro_rules! min_eval {
+    ($config:expr, $moves_made:expr) => {
+        -($config.max_player_moves() - (($moves_made + 1) >> 1))
+    };
     ($moves_made:expr) => {
         -(MAX_PLAYER_MOVES - (($moves_made + 1) >> 1))
     };
 }
 
 macro_rules! max_eval {
+    ($config:expr, $moves_made:expr) => {
+        $config.max_player_moves() - ($moves_made >> 1)
+    };
     ($moves_made:expr) => {
         MAX_PLAYER_MOVES - ($moves_made >> 1)
     };
 }
 
-pub fn is_win(pieces: u64) -> bool {
-    for i in CONNECTION_DIRECTIONS {
-        let mut connections = pieces;
+#[macro_export]
+macro_rules! get_cache_entry_eval {
+    ($cache_entry:expr) => {
+        ($cache_entry >> CACHE_VALUE_SHIFT) as i8 - MAX_PLAYER_MOVES
+    }
+}
 
-        for _ in 0..3 {
-            connections &= connections >> i;
-        }
+#[macro_export]
+macro_rules! get_cache_entry_state {
+    ($cache_entry:expr) => {
+        ($cache_entry & BOARD_MASK)
+    }
+}
 
-        if connections != 0 {
-            return true
-        }
+#[macro_export]
+macro_rules! create_cache_entry {
+    ($state:expr, $bound:expr) => {
+        $state | ((($bound + MAX_PLAYER_MOVES) as u64) << CACHE_VALUE_SHIFT)
+    };
+}
+
+// positive when the side to move wins in that many plies (counting its winning move),
+// negative when it loses in that many plies, None for a draw
+pub fn moves_to_win(eval: i8, moves_made: i8) -> Option<i8> {
+    if eval == DRAW {
+        return None;
+    }
+
+    let winner_parity = if eval > 0 { moves_made & 1 } else { (moves_made + 1) & 1 };
+    let winning_move = ((MAX_PLAYER_MOVES - eval.abs()) << 1) + winner_parity;
+    let plies = winning_move - moves_made + 1;
+
+    Some(if eval > 0 { plies } else { -plies })
+}
+
+// every column keeps at least one empty sentinel row above the board (rows < COL_BITS), so a shifted
+// run can't join the top of one column to the bottom of the next as long as no sentinel bit is set
+pub fn is_win(pieces: u64, win_len: u32) -> bool {
+    let pieces = pieces & IS_LEGAL;
+
+    connections(pieces, CONNECTION_DIRECTIONS[0], win_len)
+        | connections(pieces, CONNECTION_DIRECTIONS[1], win_len)
+        | connections(pieces, CONNECTION_DIRECTIONS[2], win_len)
+        | connections(pieces, CONNECTION_DIRECTIONS[3], win_len) != 0
+}
+
+// is_win on another board, for checking positions rather than searching them
+pub fn is_win_with(config: &BoardConfig, pieces: u64) -> bool {
+    let pieces = pieces & config.is_legal_mask();
+
+    CONNECTION_DIRECTIONS.iter().any(|&direction| connections(pieces, direction, config.win_len) != 0)
+}
+
+// the start of every run of win_len pieces in a direction, found by doubling the run length each step so a
+// run of 4 takes 2 dependent shifts instead of 3, then overlapping two runs to reach win_len exactly
+#[inline(always)]
+fn connections(pieces: u64, direction: usize, win_len: u32) -> u64 {
+    let mut connections = pieces;
+    let mut run_len = 1;
+
+    while run_len << 1 <= win_len {
+        connections &= connections >> (run_len as usize * direction);
+        run_len <<= 1;
     }
 
-    false
+    if run_len < win_len {
+        connections &= connections >> ((win_len - run_len) as usize * direction);
+    }
+
+    connections
 }
 
-fn next_legal_moves(move_order: u32, height_map: u64) -> impl Iterator<Item = (u32, u64)> {
+// move_order lists every column of DEFAULT_BOARD, a column past the edge of a narrower board has no legal cells
+fn next_legal_moves<B: Board>(board: B, move_order: u32, height_map: u64) -> impl Iterator<Item = (u32, u64)> {
+    let is_legal = board.config().is_legal_mask();
+
     (0..COLS).filter_map(move |i| {
         let col = get_col!(move_order, i);
         let next_move = open_row!(height_map, col);
 
-        if (next_move & IS_LEGAL) != 0 {
+        if (next_move & is_legal) != 0 {
             Some((col, next_move))
         } else {
             None
@@ -59,275 +146,486 @@ fn next_legal_moves(move_order: u32, height_map: u64) -> impl Iterator<Item = (u
     })
 }
 
-// unpack state struct for better performance
-pub fn evaluate_position_rec(
-    curr_pieces: u64,
-    opp_pieces: u64,
-    height_map: u64,
-    moves_made: i8,
-    mut alpha: i8,
-    mut beta: i8,
-    caches: &mut StateCaches,
-    terminate: &AtomicBool,
-    pos: &mut usize,
-) -> Option<i8> {
+fn heuristic_eval<B: Board>(board: B, curr_pieces: u64, opp_pieces: u64, height_map: u64, moves_made: i8) -> i8 {
+    let config = board.config();
+    let threats = count_threats_on(board, curr_pieces, height_map, config.win_len) as i32
+        - count_threats_on(board, opp_pieces, height_map, config.win_len) as i32;
+    let mut centrality = 0;
+
+    for i in 0..COLS {
+        let col = get_col!(DEFAULT_MOVE_ORDER, i);
+        let col_mask = COL_MASK << col_shift!(col);
+        let pieces_diff = (curr_pieces & col_mask).count_ones() as i32 - (opp_pieces & col_mask).count_ones() as i32;
 
-    if terminate.load(Ordering::Relaxed) {
-        return None
+        centrality += (COLS - i) as i32 * pieces_diff;
     }
 
-    *pos += 1;
+    let eval = HEURISTIC_THREAT_WEIGHT * threats + centrality / HEURISTIC_CENTRALITY_DIVISOR;
 
-    if moves_made == MAX_TOTAL_MOVES {
-        return Some(DRAW);
+    // stay strictly inside the exact win/loss range so an estimate never poses as a proven result
+    let lower = max(-config.max_eval(), min_eval!(config, moves_made)) as i32 + 1;
+    let upper = min(config.max_eval(), max_eval!(config, moves_made)) as i32 - 1;
+
+    eval.clamp(min(lower, 0), max(upper, 0)) as i8
+}
+
+pub fn static_eval(state: &State) -> i8 {
+    heuristic_eval(DefaultBoard, state.curr_pieces, state.opp_pieces, state.height_map, state.moves_made)
+}
+
+// the hooks evaluate_position_rec_with calls for what a search leaves to judgement; the search itself (bounds,
+// caches, immediate wins, forced moves and a full board ending the game) stays exact, so at full depth any evaluator
+// that keeps draw_score's default gives the same evals and only the node count changes. board is the board being
+// searched, DefaultBoard unless the search came in through one of the _on entry points
+pub trait Evaluator {
+    // the eval at the depth horizon, from the side to move's point of view; it has to stay strictly inside the
+    // exact win and loss evals for moves_made on board, or an estimate would pass for a proven result
+    fn static_eval<B: Board>(
+        &self,
+        board: B,
+        curr_pieces: u64,
+        opp_pieces: u64,
+        height_map: u64,
+        moves_made: i8,
+    ) -> i8;
+
+    // the order to search the columns in, packed like move_order (four bits per column, first searched first);
+    // history counts the beta cutoffs each column has caused at this ply
+    fn order_moves<B: Board>(
+        &self,
+        board: B,
+        curr_pieces: u64,
+        opp_pieces: u64,
+        height_map: u64,
+        move_order: u32,
+        history: &[u32; COLS as usize],
+    ) -> u32;
+
+    // what a full board is worth to the side to move there, a draw unless overridden; the search decides when
+    // the board is full and takes this as the exact eval there, so it has to stay within the eval bounds
+    #[inline(always)]
+    fn draw_score(&self, _curr_pieces: u64, _opp_pieces: u64, _height_map: u64) -> i8 {
+        DRAW
     }
+}
 
-    alpha = max(alpha, min_eval!(moves_made));
-    beta = min(beta, max_eval!(moves_made));
+// the exact solver: the threat and centrality heuristic at the horizon, moves ordered by the threats they make,
+// and a full board worth a draw
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultEvaluator;
+
+impl Evaluator for DefaultEvaluator {
+    #[inline(always)]
+    fn static_eval<B: Board>(
+        &self,
+        board: B,
+        curr_pieces: u64,
+        opp_pieces: u64,
+        height_map: u64,
+        moves_made: i8,
+    ) -> i8 {
+        heuristic_eval(board, curr_pieces, opp_pieces, height_map, moves_made)
+    }
 
-    let state = state_bitboard(curr_pieces, height_map);
-    let cache_index = cache_index!(state);
+    #[inline(always)]
+    fn order_moves<B: Board>(
+        &self,
+        board: B,
+        curr_pieces: u64,
+        _opp_pieces: u64,
+        height_map: u64,
+        move_order: u32,
+        history: &[u32; COLS as usize],
+    ) -> u32 {
+        let win_len = board.config().win_len;
+        let mut threats = 0;
+
+        for (col, next_move) in next_legal_moves(board, move_order, height_map) {
+            let updated_pieces = update_pieces!(curr_pieces, next_move);
+            let updated_height_map = update_height_map!(height_map, next_move);
+
+            threats |= pack_threats(col, count_threats_on(board, updated_pieces, updated_height_map, win_len));
+        }
 
-    alpha = max(alpha, caches.get_lower_bound(state, moves_made, cache_index));
-    if alpha >= beta {
-        return Some(alpha);
+        sort_by_threats_and_history(move_order, threats, history)
     }
+}
+
+// the DefaultEvaluator with a full board worth draw_score to the side to move there instead of DRAW; see
+// optimal_moves_with_contempt
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContemptEvaluator {
+    pub draw_score: i8,
+}
 
-    beta = min(beta, caches.get_upper_bound(state, moves_made, cache_index));
-    if alpha >= beta {
-        return Some(alpha);
+impl Evaluator for ContemptEvaluator {
+    #[inline(always)]
+    fn static_eval<B: Board>(
+        &self,
+        board: B,
+        curr_pieces: u64,
+        opp_pieces: u64,
+        height_map: u64,
+        moves_made: i8,
+    ) -> i8 {
+        DefaultEvaluator.static_eval(board, curr_pieces, opp_pieces, height_map, moves_made)
     }
 
-    let mut threats = 0;
-    let mut forced_move_count = 0;
-    let mut forced_move = 0;
+    #[inline(always)]
+    fn order_moves<B: Board>(
+        &self,
+        board: B,
+        curr_pieces: u64,
+        opp_pieces: u64,
+        height_map: u64,
+        move_order: u32,
+        history: &[u32; COLS as usize],
+    ) -> u32 {
+        DefaultEvaluator.order_moves(board, curr_pieces, opp_pieces, height_map, move_order, history)
+    }
 
-    for (col, next_move) in next_legal_moves(DEFAULT_MOVE_ORDER, height_map) {
-        let updated_pieces = update_pieces!(curr_pieces, next_move);
+    #[inline(always)]
+    fn draw_score(&self, _curr_pieces: u64, _opp_pieces: u64, _height_map: u64) -> i8 {
+        self.draw_score
+    }
+}
 
-        if is_win(updated_pieces) {
-            return Some(max_eval!(moves_made));
-        }
+// the transposition table the search reads bounds from and stores them in, with the base move order and the
+// killers and history it orders moves by; StateCaches with std, ArrayCaches without, which keeps no killers or
+// history and so leaves those at their defaults
+pub trait SearchCaches {
+    fn move_order(&self) -> u32;
 
-        if is_win(update_pieces!(opp_pieces, next_move)) {
-            forced_move_count += 1;
-            forced_move = next_move;
-        }
+    fn cache_index(&self, state: u64) -> usize;
+
+    fn get_lower_bound(&self, state: u64, moves_made: i8, cache_index: usize) -> i8;
+
+    fn get_upper_bound(&self, state: u64, moves_made: i8, cache_index: usize) -> i8;
 
-        let updated_height_map = update_height_map!(height_map, next_move);
+    // work is the nodes searched below the position, for caches that keep the more expensive of two entries
+    fn put_lower_bound(&mut self, bound: i8, state: u64, moves_made: i8, cache_index: usize, work: usize);
 
-        let next_state = state_bitboard(opp_pieces, updated_height_map);
+    fn put_upper_bound(&mut self, bound: i8, state: u64, moves_made: i8, cache_index: usize, work: usize);
 
-        alpha = max(alpha, -caches.get_upper_bound(
-            next_state,
-            moves_made + 1,
-            cache_index!(next_state),
-        ));
+    #[inline(always)]
+    fn killer_move(&self, _moves_made: i8) -> u64 {
+        0
+    }
+
+    #[inline(always)]
+    fn put_killer_move(&mut self, _next_move: u64, _moves_made: i8) {}
+
+    #[inline(always)]
+    fn history(&self, _moves_made: i8) -> &[u32; COLS as usize] {
+        &[0; COLS as usize]
+    }
 
-        if alpha >= beta {
-            return Some(alpha);
+    #[inline(always)]
+    fn put_history(&mut self, _col: u32, _moves_made: i8) {}
+}
+
+// the last completed iteration of a deepening search, exact when it never reached the horizon
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DeadlineEval {
+    pub eval: i8,
+    pub depth: i8,
+    pub exact: bool,
+}
+
+mod negamax;
+pub use negamax::*;
+// the search proper needs DashMap and threads; array_search is all that's left without std
+#[cfg(feature = "std")]
+mod search;
+#[cfg(feature = "std")]
+pub use search::*;
+mod array_search;
+pub use array_search::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a different seed per test, and no rand dependency for the tests to pull in
+    fn xorshift(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    fn cell(row: u32, col: u32) -> u64 {
+        1 << (row + col_shift!(col))
+    }
+
+    // walks every line of WIN_LENGTH cells on the board by row and column, so nothing can wrap
+    fn naive_is_win(pieces: u64) -> bool {
+        let (rows, cols, win_len) = (ROWS as i32, COLS as i32, WIN_LENGTH as i32);
+
+        (0..rows).any(|row| (0..cols).any(|col| {
+            [(1, 0), (-1, 1), (0, 1), (1, 1)].iter().any(|&(d_row, d_col)| (0..win_len).all(|i| {
+                let (r, c) = (row + i * d_row, col + i * d_col);
+                (0..rows).contains(&r) && (0..cols).contains(&c) && pieces & cell(r as u32, c as u32) != 0
+            }))
+        }))
+    }
+
+    fn line(start_row: u32, start_col: u32, d_row: i32, d_col: i32) -> u64 {
+        (0..WIN_LENGTH as i32).fold(0, |pieces, i| {
+            pieces | cell((start_row as i32 + i * d_row) as u32, (start_col as i32 + i * d_col) as u32)
+        })
+    }
+
+    #[test]
+    fn is_win_finds_lines_in_every_direction() {
+        assert!(is_win(line(2, 5, 1, 0), WIN_LENGTH));
+        assert!(is_win(line(6, 3, 0, 1), WIN_LENGTH));
+        assert!(is_win(line(0, 1, 1, 1), WIN_LENGTH));
+        assert!(is_win(line(6, 0, -1, 1), WIN_LENGTH));
+        assert!(is_win(line(3, 3, -1, 1), WIN_LENGTH));
+    }
+
+    #[test]
+    fn is_win_needs_the_whole_line() {
+        for (d_row, d_col) in [(1, 0), (0, 1), (1, 1), (-1, 1)] {
+            let start_row = if d_row < 0 { ROWS - 1 } else { 0 };
+            let full_line = line(start_row, 0, d_row, d_col);
+
+            assert!(is_win(full_line, WIN_LENGTH));
+            assert!(!is_win(full_line & (full_line - 1), WIN_LENGTH), "direction {d_row}, {d_col}");
+            assert!(!is_win(full_line & !(1 << (63 - full_line.leading_zeros())), WIN_LENGTH), "direction {d_row}, {d_col}");
         }
+    }
 
-        threats |= count_threats(updated_pieces, updated_height_map) << index!(col);
-    }
-
-    if forced_move_count > 1 {
-        return Some(min_eval!(moves_made));
-    }
-
-    if forced_move_count == 1 {
-        return Some(-evaluate_position_rec(
-            opp_pieces,
-            update_pieces!(curr_pieces, forced_move),
-            update_height_map!(height_map, forced_move),
-            moves_made + 1,
-            -beta,
-            -alpha,
-            caches,
-            terminate,
-            pos
-        )?);
-    }
-
-    let heuristic_move_order = sort_by_threats(threats);
-    let mut moves_searched = 0;
-
-    for (_col, next_move) in next_legal_moves(heuristic_move_order, height_map) {
-        let updated_pieces = update_pieces!(curr_pieces, next_move);
-        let updated_height_map = update_height_map!(height_map, next_move);
-
-        let eval = if moves_searched == 0 {
-            -evaluate_position_rec(
-                opp_pieces,
-                updated_pieces,
-                updated_height_map,
-                moves_made + 1,
-                -beta,
-                -alpha,
-                caches,
-                terminate,
-                pos
-            )?
-        } else {
-            let null_window_eval = -evaluate_position_rec(
-                opp_pieces,
-                updated_pieces,
-                updated_height_map,
-                moves_made + 1,
-                -alpha - 1,
-                -alpha,
-                caches,
-                terminate,
-                pos
-            )?;
-
-            if null_window_eval > alpha && null_window_eval < beta {
-                -evaluate_position_rec(
-                    opp_pieces,
-                    updated_pieces,
-                    updated_height_map,
-                    moves_made + 1,
-                    -beta,
-                    -alpha,
-                    caches,
-                    terminate,
-                    pos
-                )?
-            } else {
-                null_window_eval
-            }
+    #[test]
+    fn five_in_a_row_is_needed_when_the_win_length_is_five() {
+        let four = (0..4).fold(0, |pieces, col| pieces | cell(2, col));
+        let five = four | cell(2, 4);
+        let diagonal_five = (0..5).fold(0, |pieces, i| pieces | cell(i, i + 1));
+
+        assert!(!is_win(four, 5));
+        assert!(is_win(five, 5));
+        assert!(is_win(diagonal_five, 5));
+        assert!(!is_win(diagonal_five & !cell(2, 3), 5));
+    }
+
+    #[test]
+    fn threats_follow_the_win_length() {
+        let three = cell(0, 1) | cell(0, 2) | cell(0, 3);
+        let four = three | cell(0, 4);
+        // every column empty but the bottom row pieces
+        let height_map = |pieces: u64| {
+            (0..COLS).fold(0, |height_map, col| height_map | cell((pieces >> col_shift!(col)) as u32 & 1, col))
         };
 
-        alpha = max(alpha, eval);
+        // either end of three completes four, and only the ends of four complete five
+        assert_eq!(count_threats(three, height_map(three), 4), 2);
+        assert_eq!(count_threats(three, height_map(three), 5), 0);
+        assert_eq!(count_threats(four, height_map(four), 5), 2);
+    }
 
-        if alpha >= beta {
+    #[test]
+    fn moves_to_win_counts_plies_to_the_winning_move() {
+        // X completing four on its fourth move, the earliest any game can end
+        assert_eq!(moves_to_win(MAX_EVAL, 0), Some(7));
+        assert_eq!(moves_to_win(-MAX_EVAL, 1), Some(-6));
+        assert_eq!(moves_to_win(max_eval!(6), 6), Some(1));
+        assert_eq!(moves_to_win(min_eval!(7), 7), Some(-2));
+        assert_eq!(moves_to_win(1, MAX_TOTAL_MOVES - 1), Some(1));
+        assert_eq!(moves_to_win(DRAW, 20), None);
+    }
 
-            caches.put_lower_bound(alpha, state, moves_made, cache_index);
-            return Some(alpha);
-        }
+    #[test]
+    fn is_win_matches_a_naive_checker_on_random_boards() {
+        let mut seed = 0x2545_f491_4f6c_dd1d;
 
-        moves_searched += 1;
+        for _ in 0..20_000 {
+            let pieces = match xorshift(&mut seed) % 3 {
+                0 => xorshift(&mut seed),
+                1 => xorshift(&mut seed) & xorshift(&mut seed),
+                _ => xorshift(&mut seed) & xorshift(&mut seed) & xorshift(&mut seed),
+            };
+
+            assert_eq!(is_win(pieces, WIN_LENGTH), naive_is_win(pieces & IS_LEGAL), "pieces {pieces:#x}");
+        }
     }
 
-    caches.put_upper_bound(alpha, state, moves_made, cache_index);
-    Some(alpha)
-}
+    #[test]
+    fn runs_across_the_top_of_a_column_and_the_bottom_of_the_next_are_not_wins() {
+        for col in 0..COLS - 1 {
+            for top in 1..WIN_LENGTH {
+                let top_of_col = (ROWS - top..ROWS).fold(0, |pieces, row| pieces | cell(row, col));
+                let bottom_of_next = (0..WIN_LENGTH - top).fold(0, |pieces, row| pieces | cell(row, col + 1));
 
-pub fn evaluate_position_with_workers(game_state: State, pos: &mut usize) -> Result<i8> {
-    let mut caches = StateCaches::new();
+                assert!(!is_win(top_of_col | bottom_of_next, WIN_LENGTH), "col {col}, {top} on top");
+            }
+        }
+    }
 
-    let worker_thread_handlers = spawn_evaluate_position_worker_threads(
-        DEFAULT_NUM_WORKER_THREADS, &game_state, &caches);
+    // every run of WIN_LENGTH bits the shifts can see, including those that wrap through a sentinel row into
+    // the next column, is a win exactly when it's a line on the board
+    #[test]
+    fn every_shifted_run_is_a_win_only_when_it_is_a_line() {
+        for &direction in CONNECTION_DIRECTIONS {
+            for start in 0..u64::BITS as usize - (WIN_LENGTH as usize - 1) * direction {
+                let run = (0..WIN_LENGTH as usize).fold(0, |pieces, i| pieces | 1 << (start + i * direction));
 
-    let eval = evaluate_position_rec(
-        game_state.curr_pieces,
-        game_state.opp_pieces,
-        game_state.height_map,
-        game_state.moves_made,
-        MIN_EVAL,
-        MAX_EVAL,
-        &mut caches,
-        &AtomicBool::new(false),
-        pos,
-    ).ok_or_else(|| Connect4Error::EvaluatePositionError)?;
+                assert_eq!(is_win(run, WIN_LENGTH), naive_is_win(run & IS_LEGAL), "run {run:#x}");
+            }
+        }
+    }
 
-    for handler in &worker_thread_handlers {
-        handler.terminate();
+    // evals and best moves checked against a full solve when they were added, kept small for debug builds
+    #[cfg(feature = "std")]
+    #[test]
+    fn known_positions_solve_to_their_evals() {
+        let positions: [(&[u32], i8, &[u32]); 5] = [
+            (&[3, 2, 3, 2, 3, 2], max_eval!(6), &[3]),
+            (&[1, 6, 2, 1, 1, 2, 4, 6, 6, 1, 4, 2, 1, 2, 5, 4, 6, 6, 1, 0, 2, 6, 6, 2, 5], 8, &[3]),
+            (&[0, 5, 2, 2, 1, 0, 6, 4, 6, 0, 4, 5, 5, 0, 5, 5, 0, 6, 0, 5, 5, 0, 1], -10, &[3]),
+            (&[0, 5, 4, 5, 4, 1, 6, 6, 2, 3, 2, 4, 0, 3, 3, 0, 1, 2, 6, 2, 0, 5, 4, 1], DRAW, &[5]),
+            (&[6, 0, 4, 3, 5, 5, 6, 0, 0, 1, 1, 6, 2, 5, 6, 6, 5, 1, 2, 4, 0, 3, 1, 5], 2, &[0, 3]),
+        ];
+
+        for (moves, eval, best_moves) in positions {
+            let state = State::from_moves(moves).unwrap();
+            let optimal = optimal_moves(&state, &mut crate::caches::StateCaches::new()).unwrap();
+
+            assert_eq!(evaluate_position(&state).unwrap(), eval, "{moves:?}");
+            assert_eq!((optimal.eval, optimal.best_moves), (eval, best_moves.to_vec()), "{moves:?}");
+        }
     }
 
-    for handler in worker_thread_handlers {
-        handler.join().map_err(|_| Connect4Error::WorkerThreadJoinError)?;
+    // a win on the next move, a double threat O can only answer one side of, and the move that sets it up
+    #[cfg(feature = "std")]
+    #[test]
+    fn moves_to_win_on_solved_short_wins() {
+        let positions: [(&[u32], i8); 3] = [
+            (&[3, 2, 3, 2, 3, 2], 1),
+            (&[2, 2, 3, 3, 4], -2),
+            (&[2, 2, 3, 3], 3),
+        ];
+
+        for (moves, plies) in positions {
+            let state = State::from_moves(moves).unwrap();
+            let eval = evaluate_position(&state).unwrap();
+
+            assert_eq!(moves_to_win(eval, state.moves_made), Some(plies), "{moves:?}");
+        }
     }
 
-    Ok(eval)
-}
+    // a mirror image is the same position, so it has to solve to the same eval whether it's searched on its own
+    // or finds its reflection's bounds already in the caches
+    #[cfg(feature = "std")]
+    #[test]
+    fn mirrored_positions_solve_to_the_same_eval() {
+        let mut seed = 0x6a09_e667_f3bc_c908;
+        let mut caches = crate::caches::StateCaches::new();
+        let mut solved = 0;
 
-pub fn optimal_moves(
-    state: &State,
-    caches: &mut StateCaches,
-    pos: &mut usize,
-) -> Result<(i8, Vec<u32>)> {
+        while solved < 12 {
+            let mut state = State::start_state();
 
-    let mut best_moves = Vec::new();
-    let mut threats = 0;
+            for _ in 0..24 + xorshift(&mut seed) % 8 {
+                let legal_moves = state.legal_moves();
 
-    for (col, next_move) in next_legal_moves(DEFAULT_MOVE_ORDER, state.height_map) {
-        let updated_pieces = update_pieces!(state.curr_pieces, next_move);
+                if legal_moves.is_empty() {
+                    break;
+                }
 
-        if is_win(updated_pieces) {
-            best_moves.push(col);
-        }
+                state = state.play_move(legal_moves[(xorshift(&mut seed) % legal_moves.len() as u64) as usize]);
+            }
 
-        let updated_height_map = update_height_map!(state.height_map, next_move);
-        threats |= count_threats(updated_pieces, updated_height_map) << index!(col);
-    }
-
-    if best_moves.len() > 0 {
-        return Ok((max_eval!(state.moves_made), best_moves))
-    }
-
-    let heuristic_move_order = sort_by_threats(threats);
-    let mut state_max_eval = MIN_EVAL;
-    let unused = AtomicBool::new(false);
-
-    for (col, next_move) in next_legal_moves(heuristic_move_order, state.height_map) {
-        let mut eval = -evaluate_position_rec(
-            state.opp_pieces,
-            update_pieces!(state.curr_pieces, next_move),
-            update_height_map!(state.height_map, next_move),
-            state.moves_made + 1,
-            -state_max_eval - 1,
-            -state_max_eval + 1,
-            caches,
-            &unused,
-            pos
-        ).ok_or_else(|| Connect4Error::EvaluatePositionError)?;
-
-        println!("Initial Eval: {eval} {col}");
-
-        if eval > state_max_eval {
-            eval = -evaluate_position_rec(
-                state.opp_pieces,
-                update_pieces!(state.curr_pieces, next_move),
-                update_height_map!(state.height_map, next_move),
-                state.moves_made + 1,
-                MIN_EVAL,
-                -eval,
-                caches,
-                &unused,
-                pos
-            ).ok_or_else(|| Connect4Error::EvaluatePositionError)?;
-
-            println!("Updated Eval: {eval} {col}");
-
-            best_moves = vec![col];
-            state_max_eval = eval;
-        } else if eval == state_max_eval {
-            best_moves.push(col);
+            if state.is_terminal() {
+                continue;
+            }
+
+            let eval = evaluate_position(&state).unwrap();
+
+            assert_eq!(evaluate_position(&state.mirror()).unwrap(), eval, "{}", state.to_notation());
+            let optimal = optimal_moves(&state, &mut caches).unwrap();
+            let mirrored = optimal_moves(&state.mirror(), &mut caches).unwrap();
+            let mut reflected_best_moves: Vec<u32> = mirrored.best_moves.iter().map(|col| COLS - 1 - col).collect();
+            let mut best_moves = optimal.best_moves;
+            reflected_best_moves.sort_unstable();
+            best_moves.sort_unstable();
+
+            assert_eq!((optimal.eval, mirrored.eval), (eval, eval));
+            assert_eq!(reflected_best_moves, best_moves);
+            solved += 1;
         }
     }
 
-    Ok((state_max_eval, best_moves))
-}
+    // only the hooks that have to be written, so draw_score keeps its default
+    #[cfg(feature = "std")]
+    struct NaiveEvaluator;
+
+    #[cfg(feature = "std")]
+    impl Evaluator for NaiveEvaluator {
+        fn static_eval<B: Board>(&self, _: B, _curr_pieces: u64, _opp_pieces: u64, _height_map: u64, _: i8) -> i8 {
+            DRAW
+        }
 
-pub fn optimal_moves_with_workers(
-    state: &State,
-    caches: &mut StateCaches,
-    pos: &mut usize
-) -> Result<(i8, Vec<u32>)> {
+        fn order_moves<B: Board>(
+            &self,
+            _: B,
+            _: u64,
+            _: u64,
+            _: u64,
+            move_order: u32,
+            _: &[u32; COLS as usize],
+        ) -> u32 {
+            move_order
+        }
+    }
 
-    let worker_thread_handlers = spawn_evaluate_position_worker_threads(
-        DEFAULT_NUM_WORKER_THREADS, state, caches);
+    // 3 squares left and every way of filling them is a draw, so a full board's worth decides the eval
+    #[cfg(feature = "std")]
+    fn drawn_three_from_full() -> State {
+        State::from_moves(&[
+            4, 2, 0, 5, 5, 5, 1, 1, 6, 4, 2, 6, 4, 5, 1, 3, 3, 1, 4, 3, 4, 3, 2,
+            5, 2, 4, 6, 2, 4, 2, 2, 1, 6, 3, 3, 0, 1, 0, 1, 3, 0, 6, 0, 0, 5, 6,
+        ]).unwrap()
+    }
 
-    let best_moves = optimal_moves(state, caches, pos)?;
+    #[cfg(feature = "std")]
+    #[test]
+    fn a_full_board_is_a_draw_for_an_evaluator_that_keeps_the_default() {
+        let state = drawn_three_from_full();
 
-    for handler in &worker_thread_handlers {
-        handler.terminate();
+        assert_eq!(evaluate_position_with(&state, FULL_DEPTH, &NaiveEvaluator).unwrap().eval, DRAW);
+        assert_eq!(evaluate_position_with(&state, FULL_DEPTH, &DefaultEvaluator).unwrap().eval, DRAW);
     }
 
-    for handler in worker_thread_handlers {
-        *pos += handler.join().map_err(|_| Connect4Error::WorkerThreadJoinError)?;
+    // the full board is 3 plies away, so its worth to the side to move there is negated at the root
+    #[cfg(feature = "std")]
+    #[test]
+    fn draw_score_is_what_the_full_board_is_worth() {
+        let state = drawn_three_from_full();
+
+        for draw_score in [-1, 1] {
+            let deadline_eval = evaluate_position_with(&state, FULL_DEPTH, &ContemptEvaluator { draw_score }).unwrap();
+
+            assert_eq!(deadline_eval.eval, -draw_score);
+        }
     }
 
-    Ok(best_moves)
+    #[test]
+    fn sentinel_bits_never_complete_a_win() {
+        let sentinels = BOARD_MASK & !IS_LEGAL;
+
+        assert!(!is_win(sentinels, WIN_LENGTH));
+
+        for col in 0..COLS {
+            let under_sentinel = (ROWS - (WIN_LENGTH - 1)..ROWS).fold(0, |pieces, row| pieces | cell(row, col));
+
+            assert!(!is_win(under_sentinel | sentinels, WIN_LENGTH), "col {col}");
+        }
+
+        for row in 0..ROWS {
+            let last_cols = (COLS - (WIN_LENGTH - 1)..COLS).fold(0, |pieces, col| pieces | cell(row, col));
+
+            assert!(!is_win(last_cols | sentinels | !BOARD_MASK, WIN_LENGTH), "row {row}");
+        }
+    }
 }
@@ -0,0 +1,220 @@
+use super::*;
+use alloc::boxed::Box;
+use alloc::vec;
+use core::sync::atomic::AtomicBool;
+
+
+// two tables of u64 slots, about 1MB in all; a constrained target picks its own size with with_capacity
+pub const ARRAY_CACHE_SIZE: usize = (1 << 16) + 1;
+
+
+// the transposition table for searching without std: plain arrays indexed like the end game caches, with the
+// same entry layout, but used for the whole game since there's no DashMap for the beginning game
+pub struct ArrayCaches {
+    lower_bound_cache: Box<[u64]>,
+    upper_bound_cache: Box<[u64]>,
+    // the order columns are tried in before threats reorder them
+    pub move_order: u32,
+}
+
+impl Default for ArrayCaches {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArrayCaches {
+    pub fn new() -> Self {
+        Self::with_capacity(ARRAY_CACHE_SIZE)
+    }
+
+    pub fn with_capacity(cache_size: usize) -> Self {
+        assert!(cache_size > 0, "array cache size must be positive");
+
+        Self {
+            lower_bound_cache: vec![0; cache_size].into_boxed_slice(),
+            upper_bound_cache: vec![0; cache_size].into_boxed_slice(),
+            move_order: DEFAULT_MOVE_ORDER,
+        }
+    }
+
+    pub fn with_move_order(self, move_order: u32) -> Self {
+        Self { move_order, ..self }
+    }
+
+    pub fn cache_size(&self) -> usize {
+        self.lower_bound_cache.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.lower_bound_cache.fill(0);
+        self.upper_bound_cache.fill(0);
+    }
+}
+
+// one slot per index and no killers or history, so moves_made and work go unused; the newest bound always takes
+// the slot, like the shallow end game caches
+impl SearchCaches for ArrayCaches {
+    fn move_order(&self) -> u32 {
+        self.move_order
+    }
+
+    fn cache_index(&self, state: u64) -> usize {
+        (state % self.cache_size() as u64) as usize
+    }
+
+    fn get_lower_bound(&self, state: u64, _moves_made: i8, cache_index: usize) -> i8 {
+        array_cache_get(self.lower_bound_cache[cache_index], state).unwrap_or(MIN_EVAL)
+    }
+
+    fn get_upper_bound(&self, state: u64, _moves_made: i8, cache_index: usize) -> i8 {
+        array_cache_get(self.upper_bound_cache[cache_index], state).unwrap_or(MAX_EVAL)
+    }
+
+    fn put_lower_bound(&mut self, bound: i8, state: u64, _moves_made: i8, cache_index: usize, _work: usize) {
+        self.lower_bound_cache[cache_index] = create_cache_entry!(state, bound);
+    }
+
+    fn put_upper_bound(&mut self, bound: i8, state: u64, _moves_made: i8, cache_index: usize, _work: usize) {
+        self.upper_bound_cache[cache_index] = create_cache_entry!(state, bound);
+    }
+}
+
+fn array_cache_get(cache_entry: u64, state: u64) -> Option<i8> {
+    (get_cache_entry_state!(cache_entry) == state).then(|| get_cache_entry_eval!(cache_entry))
+}
+
+// evaluate_position_rec on one thread over ArrayCaches, without killers or history, which cost a few hundred
+// bytes per ply that a small target would rather keep
+#[allow(clippy::too_many_arguments)]
+pub fn evaluate_position_array_rec(
+    curr_pieces: u64,
+    opp_pieces: u64,
+    height_map: u64,
+    moves_made: i8,
+    alpha: i8,
+    beta: i8,
+    depth: i8,
+    caches: &mut ArrayCaches,
+    terminate: &AtomicBool,
+    horizon: &mut bool,
+    pos: &mut usize,
+) -> Option<i8> {
+    evaluate_position_rec_with_events(
+        DefaultBoard,
+        curr_pieces,
+        opp_pieces,
+        height_map,
+        moves_made,
+        alpha,
+        beta,
+        depth,
+        caches,
+        terminate,
+        horizon,
+        pos,
+        &DefaultEvaluator,
+        &mut |_| {}
+    )
+}
+
+// evaluate_position_with_deadline without threads or a clock: deepens up to max_depth, and whatever sets
+// terminate (an interrupt, a watchdog) plays the part of the timer; None if not even depth 1 finished
+pub fn evaluate_position_array(
+    state: &State,
+    max_depth: i8,
+    caches: &mut ArrayCaches,
+    terminate: &AtomicBool,
+) -> Option<DeadlineEval> {
+    let mut best_eval = None;
+    let mut pos = 0;
+
+    for depth in 1..=min(max_depth, FULL_DEPTH) {
+        let mut horizon = false;
+
+        let Some(eval) = evaluate_position_array_rec(
+            state.curr_pieces,
+            state.opp_pieces,
+            state.height_map,
+            state.moves_made,
+            MIN_EVAL,
+            MAX_EVAL,
+            depth,
+            caches,
+            terminate,
+            &mut horizon,
+            &mut pos,
+        ) else {
+            break;
+        };
+
+        best_eval = Some(DeadlineEval { eval, depth, exact: !horizon });
+
+        if !horizon {
+            break;
+        }
+    }
+
+    best_eval
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn xorshift(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    // random games stopped between 28 and 35 moves in, while they're still going
+    fn late_positions(count: usize) -> Vec<State> {
+        let mut seed = 0x9e37_79b9_7f4a_7c15;
+        let mut positions = vec![];
+
+        while positions.len() < count {
+            let moves_made = 28 + xorshift(&mut seed) % 8;
+            let mut state = State::start_state();
+
+            for _ in 0..moves_made {
+                let legal_moves = state.legal_moves();
+
+                if legal_moves.is_empty() {
+                    break;
+                }
+
+                state = state.play_move(legal_moves[(xorshift(&mut seed) % legal_moves.len() as u64) as usize]);
+            }
+
+            if !state.is_terminal() {
+                positions.push(state);
+            }
+        }
+
+        positions
+    }
+
+    // the same recursion over either caches, so only the node count can differ; a small table makes the array
+    // search overwrite entries the std search keeps
+    #[cfg(feature = "std")]
+    #[test]
+    fn array_search_agrees_with_the_std_search() {
+        for state in late_positions(12) {
+            let mut caches = ArrayCaches::with_capacity((1 << 10) + 1);
+            let array_eval = evaluate_position_array(&state, FULL_DEPTH, &mut caches, &AtomicBool::new(false)).unwrap();
+
+            assert!(array_eval.exact, "{}", state.to_notation());
+            assert_eq!(array_eval.eval, evaluate_position(&state).unwrap(), "{}", state.to_notation());
+        }
+    }
+
+    #[test]
+    fn a_terminated_array_search_has_no_eval() {
+        let state = late_positions(1).remove(0);
+
+        assert_eq!(evaluate_position_array(&state, FULL_DEPTH, &mut ArrayCaches::new(), &AtomicBool::new(true)), None);
+    }
+}
@@ -0,0 +1,221 @@
+use super::*;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+
+pub const NODES_EVENT_INTERVAL: usize = 1 << 16;
+
+
+// what evaluate_with_events reports, evals from the side to move at the root's point of view
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SearchEvent {
+    // from the null window around the best eval so far, so only a bound unless it's followed by a re-search
+    RootMoveEvaluated { col: u32, eval: i8 },
+    // the exact eval of a root move that beat the best so far
+    RootMoveResearched { col: u32, eval: i8 },
+    // the aspiration window missed and the root is searched again with a wider one
+    AspirationFailLow { alpha: i8, beta: i8 },
+    AspirationFailHigh { alpha: i8, beta: i8 },
+    // a searched move refuted the position with ply moves made
+    BetaCutoff { ply: i8 },
+    // every NODES_EVENT_INTERVAL nodes, with the total searched so far
+    NodesSearched(usize),
+}
+
+// the recursion behind every search, generic over the caches so the threaded search over StateCaches and the no_std
+// one over ArrayCaches stay the same search, and over the board so DefaultBoard's masks and bounds stay constants.
+// unpack state struct for better performance
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn evaluate_position_rec_with_events<B: Board, C: SearchCaches, E: Evaluator, F: FnMut(SearchEvent)>(
+    board: B,
+    curr_pieces: u64,
+    opp_pieces: u64,
+    height_map: u64,
+    moves_made: i8,
+    mut alpha: i8,
+    mut beta: i8,
+    depth: i8,
+    caches: &mut C,
+    terminate: &AtomicBool,
+    horizon: &mut bool,
+    pos: &mut usize,
+    evaluator: &E,
+    on_event: &mut F,
+) -> Option<i8> {
+
+    let config = board.config();
+
+    // min_eval! and max_eval! only make sense for a move count a real game can reach
+    debug_assert!((0..=config.max_total_moves()).contains(&moves_made), "moves_made {moves_made} out of range");
+
+    if terminate.load(Ordering::Relaxed) {
+        return None
+    }
+
+    *pos += 1;
+    let start_pos = *pos;
+
+    if pos.is_multiple_of(NODES_EVENT_INTERVAL) {
+        on_event(SearchEvent::NodesSearched(*pos));
+    }
+
+    if moves_made == config.max_total_moves() {
+        return Some(evaluator.draw_score(curr_pieces, opp_pieces, height_map));
+    }
+
+    alpha = max(alpha, min_eval!(config, moves_made));
+    beta = min(beta, max_eval!(config, moves_made));
+
+    let state = state_bitboard_on(board, curr_pieces, height_map);
+    let cache_index = caches.cache_index(state);
+
+    alpha = max(alpha, caches.get_lower_bound(state, moves_made, cache_index));
+    if alpha >= beta {
+        return Some(alpha);
+    }
+
+    beta = min(beta, caches.get_upper_bound(state, moves_made, cache_index));
+    if alpha >= beta {
+        return Some(alpha);
+    }
+
+    if immediate_threats_on(board, curr_pieces, height_map) != 0 {
+        return Some(max_eval!(config, moves_made));
+    }
+
+    // at most one open cell per column, so each bit is a distinct move the opponent threatens to win with
+    let forced_moves = immediate_threats_on(board, opp_pieces, height_map);
+
+    for (_, next_move) in next_legal_moves(board, caches.move_order(), height_map) {
+        let updated_height_map = update_height_map!(height_map, next_move);
+
+        // the child's side to move owns opp_pieces, so this is the same canonical key the child puts its bounds under
+        let next_state = state_bitboard_on(board, opp_pieces, updated_height_map);
+
+        alpha = max(alpha, -caches.get_upper_bound(
+            next_state,
+            moves_made + 1,
+            caches.cache_index(next_state),
+        ));
+
+        if alpha >= beta {
+            return Some(alpha);
+        }
+    }
+
+    if forced_moves.count_ones() > 1 {
+        return Some(min_eval!(config, moves_made));
+    }
+
+    if forced_moves != 0 {
+        return Some(-evaluate_position_rec_with_events(
+            board,
+            opp_pieces,
+            update_pieces!(curr_pieces, forced_moves),
+            update_height_map!(height_map, forced_moves),
+            moves_made + 1,
+            -beta,
+            -alpha,
+            depth - 1,
+            caches,
+            terminate,
+            horizon,
+            pos,
+            evaluator,
+            on_event
+        )?);
+    }
+
+    if depth <= 0 {
+        *horizon = true;
+        return Some(evaluator.static_eval(board, curr_pieces, opp_pieces, height_map, moves_made));
+    }
+
+    // the killer is the square that last caused a cutoff at this ply, so it only applies if it's playable here
+    let killer_move = caches.killer_move(moves_made) & height_map;
+    let killer_col = if killer_move != 0 { killer_move.trailing_zeros() / COL_BITS as u32 } else { COLS };
+    let ordered_moves = evaluator.order_moves(
+        board, curr_pieces, opp_pieces, height_map, caches.move_order(), caches.history(moves_made));
+    let heuristic_move_order = promote_col(ordered_moves, killer_col, 1);
+
+    // each child is rebuilt from its move with update_pieces! and update_height_map!, an or and an add, rather
+    // than kept from the first loop
+    for (moves_searched, (col, next_move)) in next_legal_moves(board, heuristic_move_order, height_map).enumerate() {
+        let updated_pieces = update_pieces!(curr_pieces, next_move);
+        let updated_height_map = update_height_map!(height_map, next_move);
+
+        let eval = if moves_searched == 0 {
+            -evaluate_position_rec_with_events(
+                board,
+                opp_pieces,
+                updated_pieces,
+                updated_height_map,
+                moves_made + 1,
+                -beta,
+                -alpha,
+                depth - 1,
+                caches,
+                terminate,
+                horizon,
+                pos,
+                evaluator,
+                on_event
+            )?
+        } else {
+            let null_window_eval = -evaluate_position_rec_with_events(
+                board,
+                opp_pieces,
+                updated_pieces,
+                updated_height_map,
+                moves_made + 1,
+                -alpha - 1,
+                -alpha,
+                depth - 1,
+                caches,
+                terminate,
+                horizon,
+                pos,
+                evaluator,
+                on_event
+            )?;
+
+            if null_window_eval > alpha && null_window_eval < beta {
+                -evaluate_position_rec_with_events(
+                    board,
+                    opp_pieces,
+                    updated_pieces,
+                    updated_height_map,
+                    moves_made + 1,
+                    -beta,
+                    -alpha,
+                    depth - 1,
+                    caches,
+                    terminate,
+                    horizon,
+                    pos,
+                    evaluator,
+                    on_event
+                )?
+            } else {
+                null_window_eval
+            }
+        };
+
+        alpha = max(alpha, eval);
+
+        if alpha >= beta {
+            on_event(SearchEvent::BetaCutoff { ply: moves_made });
+            caches.put_killer_move(next_move, moves_made);
+            caches.put_history(col, moves_made);
+
+            if !*horizon {
+                caches.put_lower_bound(alpha, state, moves_made, cache_index, *pos - start_pos);
+            }
+            return Some(alpha);
+        }
+    }
+
+    if !*horizon {
+        caches.put_upper_bound(alpha, state, moves_made, cache_index, *pos - start_pos);
+    }
+    Some(alpha)
+}
@@ -0,0 +1,1268 @@
+use super::*;
+use crate::book::lookup_book;
+use crate::caches::{ReadOnlyCaches, StateCaches};
+use crate::cancellation::CancellationToken;
+use crate::database::read_databases_into_caches;
+use crate::error::{Connect4Error, Result};
+use crate::worker_threads::*;
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+
+const ASPIRATION_WINDOW: i8 = 2;
+const EASY_MAX_DEPTH: i8 = 1;
+const MEDIUM_MAX_DEPTH: i8 = 4;
+#[cfg(feature = "rand")]
+const EASY_RANDOM_MOVE_RATIO: u32 = 5;
+
+
+// the search without events; the no-op callback compiles away, so it costs nothing over having no callback
+#[allow(clippy::too_many_arguments)]
+pub fn evaluate_position_rec_with<E: Evaluator>(
+    curr_pieces: u64,
+    opp_pieces: u64,
+    height_map: u64,
+    moves_made: i8,
+    alpha: i8,
+    beta: i8,
+    depth: i8,
+    caches: &mut StateCaches,
+    terminate: &AtomicBool,
+    horizon: &mut bool,
+    pos: &mut usize,
+    evaluator: &E,
+) -> Option<i8> {
+    evaluate_position_rec_with_events(
+        DefaultBoard,
+        curr_pieces,
+        opp_pieces,
+        height_map,
+        moves_made,
+        alpha,
+        beta,
+        depth,
+        caches,
+        terminate,
+        horizon,
+        pos,
+        evaluator,
+        &mut |_| {}
+    )
+}
+
+// evaluate_position_rec_with the DefaultEvaluator, the exact solver every other search in the crate runs
+#[allow(clippy::too_many_arguments)]
+pub fn evaluate_position_rec(
+    curr_pieces: u64,
+    opp_pieces: u64,
+    height_map: u64,
+    moves_made: i8,
+    alpha: i8,
+    beta: i8,
+    depth: i8,
+    caches: &mut StateCaches,
+    terminate: &AtomicBool,
+    horizon: &mut bool,
+    pos: &mut usize,
+) -> Option<i8> {
+    evaluate_position_rec_with(
+        curr_pieces,
+        opp_pieces,
+        height_map,
+        moves_made,
+        alpha,
+        beta,
+        depth,
+        caches,
+        terminate,
+        horizon,
+        pos,
+        &DefaultEvaluator
+    )
+}
+
+
+// runs entirely on the calling thread with fresh caches, for small positions and targets without threads
+pub fn evaluate_position(state: &State) -> Result<i8> {
+    let mut caches = StateCaches::new();
+
+    evaluate_position_rec(
+        state.curr_pieces,
+        state.opp_pieces,
+        state.height_map,
+        state.moves_made,
+        MIN_EVAL,
+        MAX_EVAL,
+        FULL_DEPTH,
+        &mut caches,
+        &AtomicBool::new(false),
+        &mut false,
+        &mut 0,
+    ).ok_or(Connect4Error::EvaluatePositionError)
+}
+
+// evaluate_position on another board, see check_board for which ones the search can take
+pub fn evaluate_position_on(config: &BoardConfig, state: &State) -> Result<i8> {
+    check_board(config, state)?;
+
+    let mut caches = StateCaches::new();
+
+    evaluate_position_rec_with_events(
+        *config,
+        state.curr_pieces,
+        state.opp_pieces,
+        state.height_map,
+        state.moves_made,
+        -config.max_eval(),
+        config.max_eval(),
+        FULL_DEPTH,
+        &mut caches,
+        &AtomicBool::new(false),
+        &mut false,
+        &mut 0,
+        &DefaultEvaluator,
+        &mut |_| {}
+    ).ok_or(Connect4Error::EvaluatePositionError)
+}
+
+// the killers, history and cache entries are sized for DEFAULT_BOARD, and an empty cache slot reads as MIN_EVAL or
+// MAX_EVAL, so a board has to fit inside it and can't have a wider eval range (as a shorter win_len gives); the
+// state has to be a position on that board
+fn check_board(config: &BoardConfig, state: &State) -> Result<()> {
+    if config.rows > ROWS || config.cols > COLS || config.max_eval() > MAX_EVAL {
+        return Err(Connect4Error::UnsupportedBoard { rows: config.rows, cols: config.cols, win_len: config.win_len });
+    }
+
+    State::from_parts_with(config, state.curr_pieces, state.opp_pieces, state.height_map, state.moves_made)?;
+
+    Ok(())
+}
+
+// one search depth plies deep on the calling thread with fresh caches, judged by evaluator; exact when it never
+// reached the horizon, and then the same eval as evaluate_position gives, whatever the evaluator
+pub fn evaluate_position_with<E: Evaluator>(state: &State, depth: i8, evaluator: &E) -> Result<DeadlineEval> {
+    let mut caches = StateCaches::new();
+    let mut horizon = false;
+
+    let eval = evaluate_position_rec_with(
+        state.curr_pieces,
+        state.opp_pieces,
+        state.height_map,
+        state.moves_made,
+        MIN_EVAL,
+        MAX_EVAL,
+        depth,
+        &mut caches,
+        &AtomicBool::new(false),
+        &mut horizon,
+        &mut 0,
+        evaluator,
+    ).ok_or(Connect4Error::EvaluatePositionError)?;
+
+    Ok(DeadlineEval { eval, depth, exact: !horizon })
+}
+
+// fail-soft: a result strictly between alpha and beta is the exact eval, one at or below alpha only bounds the
+// eval from above and one at or above beta only bounds it from below, e.g. evaluate_window(state, -1, 1) >= 0
+// answers whether the side to move at least draws far quicker than a full solve
+pub fn evaluate_window(state: &State, alpha: i8, beta: i8) -> Result<i8> {
+    if alpha >= beta {
+        return Err(Connect4Error::EmptyWindow { alpha, beta });
+    }
+
+    let mut caches = StateCaches::new();
+
+    evaluate_position_rec(
+        state.curr_pieces,
+        state.opp_pieces,
+        state.height_map,
+        state.moves_made,
+        alpha,
+        beta,
+        FULL_DEPTH,
+        &mut caches,
+        &AtomicBool::new(false),
+        &mut false,
+        &mut 0,
+    ).ok_or(Connect4Error::EvaluatePositionError)
+}
+
+// from the side to move's point of view
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Wdl {
+    Win,
+    Draw,
+    Loss,
+}
+
+// a [-1, 1] window is enough to tell the three apart, a result of 1 or -1 may be a bound but its sign is exact;
+// kept over two null window probes at 0, which the lost position in benches/search.rs takes about a third longer on
+pub fn wdl(state: &State) -> Result<Wdl> {
+    let eval = evaluate_window(state, -1, 1)?;
+
+    Ok(if eval > DRAW {
+        Wdl::Win
+    } else if eval < DRAW {
+        Wdl::Loss
+    } else {
+        Wdl::Draw
+    })
+}
+
+// nodes counts every position searched, by the calling thread and by any workers
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SearchResult {
+    pub eval: i8,
+    pub nodes: usize,
+}
+
+// safe to call from any number of threads at once, nothing the search learns is written back to the caches;
+// each call allocates its own search_caches
+pub fn evaluate_position_read_only(state: &State, caches: &ReadOnlyCaches) -> Result<SearchResult> {
+    evaluate_position_read_only_with(state, &mut caches.search_caches())
+}
+
+// evaluate_position_read_only with search caches from ReadOnlyCaches::search_caches, which can be reused by one
+// thread across queries instead of allocating them for each
+pub fn evaluate_position_read_only_with(state: &State, search_caches: &mut StateCaches) -> Result<SearchResult> {
+    let mut nodes = 0;
+
+    let eval = evaluate_position_rec(
+        state.curr_pieces,
+        state.opp_pieces,
+        state.height_map,
+        state.moves_made,
+        MIN_EVAL,
+        MAX_EVAL,
+        FULL_DEPTH,
+        search_caches,
+        &AtomicBool::new(false),
+        &mut false,
+        &mut nodes,
+    ).ok_or(Connect4Error::EvaluatePositionError)?;
+
+    Ok(SearchResult { eval, nodes })
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OptimalMoves {
+    pub eval: i8,
+    pub best_moves: Vec<u32>,
+    pub nodes: usize,
+}
+
+pub fn evaluate_position_with_workers(game_state: State) -> Result<SearchResult> {
+    evaluate_position_with_num_workers(game_state, default_num_worker_threads())
+}
+
+pub fn evaluate_position_with_num_workers(game_state: State, num_workers: usize) -> Result<SearchResult> {
+    evaluate_position_with_num_workers_until(game_state, num_workers, &CancellationToken::new())
+}
+
+// evaluate_position_with_workers, but giving up with EvaluatePositionError soon after cancel is cancelled, which
+// stops the calling thread's search and every worker at once
+pub fn evaluate_position_with_workers_until(game_state: State, cancel: &CancellationToken) -> Result<SearchResult> {
+    evaluate_position_with_num_workers_until(game_state, default_num_worker_threads(), cancel)
+}
+
+pub fn evaluate_position_with_num_workers_until(
+    game_state: State,
+    num_workers: usize,
+    cancel: &CancellationToken,
+) -> Result<SearchResult> {
+    let mut caches = StateCaches::new();
+    let mut nodes = 0;
+
+    let workers_cancel = cancel.child();
+    let worker_thread_handlers = spawn_evaluate_position_worker_threads(
+        num_workers, &game_state, &caches, &workers_cancel);
+
+    let eval = evaluate_position_rec(
+        game_state.curr_pieces,
+        game_state.opp_pieces,
+        game_state.height_map,
+        game_state.moves_made,
+        MIN_EVAL,
+        MAX_EVAL,
+        FULL_DEPTH,
+        &mut caches,
+        cancel.flag(),
+        &mut false,
+        &mut nodes,
+    );
+
+    workers_cancel.cancel();
+
+    for handler in worker_thread_handlers {
+        nodes += handler.join()?;
+    }
+
+    Ok(SearchResult { eval: eval.ok_or(Connect4Error::EvaluatePositionError)?, nodes })
+}
+
+pub fn evaluate_batch(states: &[State]) -> Result<Vec<i8>> {
+    evaluate_batch_with_num_threads(states, default_num_worker_threads() + 1)
+}
+
+// each thread takes the next unclaimed position until the batch runs out, and every thread shares the same
+// caches for the whole batch, so later positions reuse what earlier ones found
+pub fn evaluate_batch_with_num_threads(states: &[State], num_threads: usize) -> Result<Vec<i8>> {
+    let caches = StateCaches::new();
+    let next = AtomicUsize::new(0);
+    let unused = AtomicBool::new(false);
+    let mut evals = vec![DRAW; states.len()];
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..num_threads.max(1)).map(|_| scope.spawn(|| -> Result<Vec<(usize, i8)>> {
+            let mut thread_caches = caches.with_shared_caches();
+            let mut thread_evals = Vec::new();
+
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(state) = states.get(index) else {
+                    break;
+                };
+
+                let eval = evaluate_position_rec(
+                    state.curr_pieces,
+                    state.opp_pieces,
+                    state.height_map,
+                    state.moves_made,
+                    MIN_EVAL,
+                    MAX_EVAL,
+                    FULL_DEPTH,
+                    &mut thread_caches,
+                    &unused,
+                    &mut false,
+                    &mut 0,
+                ).ok_or(Connect4Error::EvaluatePositionError)?;
+
+                thread_evals.push((index, eval));
+            }
+
+            Ok(thread_evals)
+        })).collect();
+
+        for handle in handles {
+            for (index, eval) in handle.join().map_err(worker_thread_panic)?? {
+                evals[index] = eval;
+            }
+        }
+
+        Ok::<_, Connect4Error>(())
+    })?;
+
+    Ok(evals)
+}
+
+pub fn evaluate_position_with_deadline(state: &State, budget: Duration) -> Result<DeadlineEval> {
+    let terminate = Arc::new(AtomicBool::new(false));
+    let timer_terminate = terminate.clone();
+    let (finished_sender, finished_receiver) = mpsc::channel::<()>();
+
+    let timer = thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) = finished_receiver.recv_timeout(budget) {
+            timer_terminate.store(true, Ordering::Relaxed);
+        }
+    });
+
+    let mut caches = StateCaches::new();
+    let mut best_eval = None;
+    let mut pos = 0;
+
+    // deepen until a search completes without reaching the horizon, i.e. the eval is exact
+    for depth in 1..=FULL_DEPTH {
+        let mut horizon = false;
+
+        let Some(eval) = evaluate_position_rec(
+            state.curr_pieces,
+            state.opp_pieces,
+            state.height_map,
+            state.moves_made,
+            MIN_EVAL,
+            MAX_EVAL,
+            depth,
+            &mut caches,
+            &terminate,
+            &mut horizon,
+            &mut pos,
+        ) else {
+            break;
+        };
+
+        best_eval = Some(DeadlineEval { eval, depth, exact: !horizon });
+
+        if !horizon {
+            break;
+        }
+    }
+
+    drop(finished_sender);
+    timer.join().map_err(worker_thread_panic)?;
+
+    best_eval.ok_or(Connect4Error::EvaluatePositionError)
+}
+
+pub fn optimal_moves(state: &State, caches: &mut StateCaches) -> Result<OptimalMoves> {
+    let mut nodes = 0;
+    let (eval, best_moves) = optimal_moves_to_depth(
+        state, FULL_DEPTH, None, caches, &AtomicBool::new(false), &mut false, &mut nodes)?;
+
+    Ok(OptimalMoves { eval, best_moves, nodes })
+}
+
+// optimal_moves on another board, see check_board; caches may only hold bounds from searches on the same board,
+// so never ones read from the databases, which are all DEFAULT_BOARD's
+pub fn optimal_moves_on(config: &BoardConfig, state: &State, caches: &mut StateCaches) -> Result<OptimalMoves> {
+    check_board(config, state)?;
+
+    let mut nodes = 0;
+    let (eval, best_moves) = optimal_moves_to_depth_with_events(
+        *config,
+        state,
+        FULL_DEPTH,
+        None,
+        caches,
+        &AtomicBool::new(false),
+        &mut false,
+        &mut nodes,
+        &DefaultEvaluator,
+        &mut |_| {}
+    )?;
+
+    Ok(OptimalMoves { eval, best_moves, nodes })
+}
+
+// the same solve as optimal_moves with fresh caches on the calling thread, reporting what the search does as it
+// goes; on_event runs on the search's own stack, so a slow callback slows the search down
+pub fn evaluate_with_events(state: &State, mut on_event: impl FnMut(SearchEvent)) -> Result<OptimalMoves> {
+    let mut caches = StateCaches::new();
+    let mut nodes = 0;
+    let (eval, best_moves) = optimal_moves_to_depth_with_events(
+        DefaultBoard,
+        state,
+        FULL_DEPTH,
+        None,
+        &mut caches,
+        &AtomicBool::new(false),
+        &mut false,
+        &mut nodes,
+        &DefaultEvaluator,
+        &mut on_event
+    )?;
+
+    Ok(OptimalMoves { eval, best_moves, nodes })
+}
+
+// contempt is how much worse than a draw the side to move takes a draw to be, so a positive contempt steers
+// away from drawn lines and a negative one towards them, and 0 is the strict game value every other search
+// uses. This can make the engine play a move that's worse by strict game value: evals count whole moves, so
+// a contempt of 1 already ranks a draw level with losing on the last move, and nothing outside [-1, 1] keeps
+// the eval bounds the search prunes with. Always fresh caches and no databases, their bounds assume a draw is 0
+pub fn optimal_moves_with_contempt(state: &State, contempt: i8) -> Result<OptimalMoves> {
+    if !(-1..=1).contains(&contempt) {
+        return Err(Connect4Error::InvalidContempt(contempt));
+    }
+
+    // the side to move on a full board is the same in every line, so whether it's the root's side is too
+    let draw_score = if (MAX_TOTAL_MOVES - state.moves_made) & 1 == 0 { -contempt } else { contempt };
+    let mut nodes = 0;
+    let (eval, best_moves) = optimal_moves_to_depth_with_events(
+        DefaultBoard,
+        state,
+        FULL_DEPTH,
+        None,
+        &mut StateCaches::new(),
+        &AtomicBool::new(false),
+        &mut false,
+        &mut nodes,
+        &ContemptEvaluator { draw_score },
+        &mut |_| {}
+    )?;
+
+    Ok(OptimalMoves { eval, best_moves, nodes })
+}
+
+// an exact root eval and its best moves, or which side of the window the eval fell on
+enum RootSearch {
+    Exact(i8, Vec<u32>),
+    FailLow,
+    FailHigh,
+}
+
+pub fn optimal_moves_to_depth(
+    state: &State,
+    depth: i8,
+    previous_eval: Option<i8>,
+    caches: &mut StateCaches,
+    terminate: &AtomicBool,
+    horizon: &mut bool,
+    pos: &mut usize,
+) -> Result<(i8, Vec<u32>)> {
+    optimal_moves_to_depth_with_events(
+        DefaultBoard, state, depth, previous_eval, caches, terminate, horizon, pos, &DefaultEvaluator, &mut |_| {})
+}
+
+#[allow(clippy::too_many_arguments)]
+fn optimal_moves_to_depth_with_events<B: Board, E: Evaluator, F: FnMut(SearchEvent)>(
+    board: B,
+    state: &State,
+    depth: i8,
+    previous_eval: Option<i8>,
+    caches: &mut StateCaches,
+    terminate: &AtomicBool,
+    horizon: &mut bool,
+    pos: &mut usize,
+    evaluator: &E,
+    on_event: &mut F,
+) -> Result<(i8, Vec<u32>)> {
+
+    let config = board.config();
+    let mut best_moves = Vec::new();
+    let mut threats = 0;
+    let winning_moves = immediate_threats_on(board, state.curr_pieces, state.height_map);
+
+    for (col, next_move) in next_legal_moves(board, caches.move_order(), state.height_map) {
+        let updated_pieces = update_pieces!(state.curr_pieces, next_move);
+
+        if (winning_moves & next_move) != 0 {
+            best_moves.push(col);
+        }
+
+        let updated_height_map = update_height_map!(state.height_map, next_move);
+        threats |= pack_threats(col, count_threats_on(board, updated_pieces, updated_height_map, config.win_len));
+    }
+
+    if !best_moves.is_empty() {
+        return Ok((max_eval!(config, state.moves_made), best_moves))
+    }
+
+    let heuristic_move_order = sort_by_threats_from(caches.move_order(), threats);
+
+    // aspiration: start from a narrow window around the previous iteration's eval and double the side
+    // it fails on, a window that reaches the board's min or max eval can't fail on that side any more
+    let max_eval = config.max_eval();
+    let mut window = ASPIRATION_WINDOW;
+    let (mut alpha, mut beta) = match previous_eval {
+        Some(eval) => (max(eval - window, -max_eval), min(eval + window, max_eval)),
+        None => (-max_eval, max_eval),
+    };
+
+    loop {
+        match optimal_moves_in_window(
+            board, state, heuristic_move_order, alpha, beta, depth, caches, terminate, horizon, pos, evaluator, on_event
+        )? {
+            RootSearch::Exact(eval, best_moves) => return Ok((eval, best_moves)),
+            RootSearch::FailLow => {
+                on_event(SearchEvent::AspirationFailLow { alpha, beta });
+                alpha = max(alpha.saturating_sub(window), -max_eval);
+            },
+            RootSearch::FailHigh => {
+                on_event(SearchEvent::AspirationFailHigh { alpha, beta });
+                beta = min(beta.saturating_add(window), max_eval);
+            },
+        }
+
+        window = window.saturating_mul(2);
+    }
+}
+
+// every root move is first tried with a window just around the best eval so far, which only tells
+// whether it's better, equal or worse, and only better moves are searched again for their exact eval
+#[allow(clippy::too_many_arguments)]
+fn optimal_moves_in_window<B: Board, E: Evaluator, F: FnMut(SearchEvent)>(
+    board: B,
+    state: &State,
+    move_order: u32,
+    alpha: i8,
+    beta: i8,
+    depth: i8,
+    caches: &mut StateCaches,
+    terminate: &AtomicBool,
+    horizon: &mut bool,
+    pos: &mut usize,
+    evaluator: &E,
+    on_event: &mut F,
+) -> Result<RootSearch> {
+
+    let max_eval = board.config().max_eval();
+    let mut best_moves = Vec::new();
+    let mut state_max_eval = alpha;
+
+    for (col, next_move) in next_legal_moves(board, move_order, state.height_map) {
+        let mut eval = -evaluate_position_rec_with_events(
+            board,
+            state.opp_pieces,
+            update_pieces!(state.curr_pieces, next_move),
+            update_height_map!(state.height_map, next_move),
+            state.moves_made + 1,
+            -state_max_eval - 1,
+            -state_max_eval + 1,
+            depth - 1,
+            caches,
+            terminate,
+            horizon,
+            pos,
+            evaluator,
+            on_event
+        ).ok_or(Connect4Error::EvaluatePositionError)?;
+
+        on_event(SearchEvent::RootMoveEvaluated { col, eval });
+
+        if eval > state_max_eval {
+            eval = -evaluate_position_rec_with_events(
+                board,
+                state.opp_pieces,
+                update_pieces!(state.curr_pieces, next_move),
+                update_height_map!(state.height_map, next_move),
+                state.moves_made + 1,
+                -beta,
+                -eval,
+                depth - 1,
+                caches,
+                terminate,
+                horizon,
+                pos,
+                evaluator,
+                on_event
+            ).ok_or(Connect4Error::EvaluatePositionError)?;
+
+            on_event(SearchEvent::RootMoveResearched { col, eval });
+
+            if eval >= beta && beta < max_eval {
+                return Ok(RootSearch::FailHigh);
+            }
+
+            best_moves = vec![col];
+            state_max_eval = eval;
+        } else if eval == state_max_eval {
+            best_moves.push(col);
+        }
+    }
+
+    if best_moves.is_empty() && alpha > -max_eval {
+        return Ok(RootSearch::FailLow);
+    }
+
+    Ok(RootSearch::Exact(state_max_eval, best_moves))
+}
+
+// solves every legal child with a full window, so it costs roughly COLS times a single solve
+pub fn rank_moves(state: &State, caches: &mut StateCaches) -> Result<Vec<(u32, i8)>> {
+    let mut ranked_moves = Vec::new();
+    let unused = AtomicBool::new(false);
+
+    for (col, next_move) in next_legal_moves(DefaultBoard, caches.move_order(), state.height_map) {
+        let updated_pieces = update_pieces!(state.curr_pieces, next_move);
+
+        let eval = if is_win(updated_pieces, WIN_LENGTH) {
+            max_eval!(state.moves_made)
+        } else {
+            -evaluate_position_rec(
+                state.opp_pieces,
+                updated_pieces,
+                update_height_map!(state.height_map, next_move),
+                state.moves_made + 1,
+                MIN_EVAL,
+                MAX_EVAL,
+                FULL_DEPTH,
+                caches,
+                &unused,
+                &mut false,
+                &mut 0
+            ).ok_or(Connect4Error::EvaluatePositionError)?
+        };
+
+        ranked_moves.push((col, eval));
+    }
+
+    ranked_moves.sort_by_key(|&(_, eval)| Reverse(eval));
+
+    Ok(ranked_moves)
+}
+
+// every move other than the best ones, with its eval and the principal variation by which the opponent
+// refutes it, starting with the opponent's reply; explains what is wrong with each rejected move
+pub fn refutations(state: &State, caches: &mut StateCaches) -> Result<Vec<(u32, i8, Vec<u32>)>> {
+    let ranked_moves = rank_moves(state, caches)?;
+    let Some(&(_, best_eval)) = ranked_moves.first() else {
+        return Ok(Vec::new());
+    };
+
+    ranked_moves.into_iter()
+        .filter(|&(_, eval)| eval < best_eval)
+        .map(|(col, eval)| {
+            let refutation = principal_variation(&state.play_move(col), -eval, caches)?;
+            Ok((col, eval, refutation))
+        })
+        .collect()
+}
+
+// every legal move leaves the opponent a winning reply: either the opponent already threatens two cells, or
+// blocking its one threat opens another right above; false when the mover can win first or the game is over
+pub fn is_lost_in_one(state: &State) -> bool {
+    lost_in_one(DefaultBoard, state)
+}
+
+pub fn is_lost_in_one_on(config: &BoardConfig, state: &State) -> bool {
+    lost_in_one(*config, state)
+}
+
+fn lost_in_one<B: Board>(board: B, state: &State) -> bool {
+    let config = board.config();
+
+    if state.is_terminal_with(&config) || immediate_threats_on(board, state.curr_pieces, state.height_map) != 0 {
+        return false;
+    }
+
+    next_legal_moves(board, DEFAULT_MOVE_ORDER, state.height_map).all(|(_, next_move)| {
+        immediate_threats_on(board, state.opp_pieces, update_height_map!(state.height_map, next_move)) != 0
+    })
+}
+
+// a fork is a move that doesn't win on the spot but leaves the opponent unable to stop the next one: after it
+// the mover either has two winning cells open at once, or one open with another directly above it, which
+// blocking the first only makes playable; a move that lets the opponent win right away doesn't count
+pub fn find_forks(state: &State) -> Vec<u32> {
+    forks(DefaultBoard, state)
+}
+
+pub fn find_forks_on(config: &BoardConfig, state: &State) -> Vec<u32> {
+    forks(*config, state)
+}
+
+fn forks<B: Board>(board: B, state: &State) -> Vec<u32> {
+    let config = board.config();
+
+    if state.is_terminal_with(&config) {
+        return vec![];
+    }
+
+    next_legal_moves(board, DEFAULT_MOVE_ORDER, state.height_map).filter_map(|(col, next_move)| {
+        let updated_pieces = update_pieces!(state.curr_pieces, next_move);
+        let updated_height_map = update_height_map!(state.height_map, next_move);
+
+        let opp_threats = immediate_threats_on(board, state.opp_pieces, updated_height_map);
+
+        if is_win_with(&config, updated_pieces) || opp_threats != 0 {
+            return None;
+        }
+
+        let open_threats = immediate_threats_on(board, updated_pieces, updated_height_map);
+        let stacked_threats = (open_threats << 1) & threat_squares_on(board, updated_pieces, config.win_len);
+
+        (open_threats.count_ones() > 1 || stacked_threats != 0).then_some(col)
+    }).collect()
+}
+
+// yields the eval and best moves at each depth up to max_depth, stopping early once a depth is searched without
+// reaching the horizon, as deeper ones would give the same exact result; the first error ends the iteration, and
+// a finished game yields GameOver as it has no moves to search
+pub fn iterative_deepening(state: &State, max_depth: i8) -> impl Iterator<Item = Result<(i8, Vec<u32>)>> {
+    let state = state.clone();
+    let mut caches = StateCaches::new();
+    let mut depth = 0;
+    let mut done = false;
+    let mut previous_eval = None;
+
+    std::iter::from_fn(move || {
+        if done || depth >= max_depth {
+            return None;
+        }
+
+        if state.is_terminal() {
+            done = true;
+            return Some(Err(Connect4Error::GameOver));
+        }
+
+        depth += 1;
+        let mut horizon = false;
+
+        let best_moves = optimal_moves_to_depth(
+            &state, depth, previous_eval, &mut caches, &AtomicBool::new(false), &mut horizon, &mut 0);
+
+        match &best_moves {
+            Ok((eval, _)) => {
+                done = !horizon;
+                previous_eval = Some(*eval);
+            },
+            Err(_) => done = true,
+        }
+
+        Some(best_moves)
+    })
+}
+
+pub fn optimal_moves_single(state: &State) -> Result<OptimalMoves> {
+    optimal_moves(state, &mut StateCaches::new())
+}
+
+pub fn optimal_moves_with_workers(state: &State, caches: &mut StateCaches) -> Result<OptimalMoves> {
+    optimal_moves_with_num_workers(state, default_num_worker_threads(), caches)
+}
+
+pub fn optimal_moves_with_num_workers(
+    state: &State,
+    num_workers: usize,
+    caches: &mut StateCaches,
+) -> Result<OptimalMoves> {
+    optimal_moves_with_num_workers_until(state, num_workers, caches, &CancellationToken::new())
+}
+
+// the workers are stopped and joined however the search ends, including when cancel cuts it short
+fn optimal_moves_with_num_workers_until(
+    state: &State,
+    num_workers: usize,
+    caches: &mut StateCaches,
+    cancel: &CancellationToken,
+) -> Result<OptimalMoves> {
+
+    let workers_cancel = cancel.child();
+    let worker_thread_handlers = spawn_evaluate_position_worker_threads(
+        num_workers, state, caches, &workers_cancel);
+
+    let mut nodes = 0;
+    let search_result = optimal_moves_to_depth(
+        state, FULL_DEPTH, None, caches, cancel.flag(), &mut false, &mut nodes);
+
+    workers_cancel.cancel();
+
+    for handler in worker_thread_handlers {
+        nodes += handler.join()?;
+    }
+
+    let (eval, best_moves) = search_result?;
+
+    Ok(OptimalMoves { eval, best_moves, nodes })
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Solution {
+    pub eval: i8,
+    pub best_moves: Vec<u32>,
+    pub principal_variation: Vec<u32>,
+    pub nodes: usize,
+}
+
+fn principal_variation_move(
+    state: &State,
+    eval: i8,
+    caches: &mut StateCaches,
+    terminate: &AtomicBool,
+    pos: &mut usize,
+) -> Result<Option<u32>> {
+
+    let mut forced_move = None;
+
+    for (col, next_move) in next_legal_moves(DefaultBoard, caches.move_order(), state.height_map) {
+        if is_win(update_pieces!(state.curr_pieces, next_move), WIN_LENGTH) {
+            return Ok(Some(col));
+        }
+
+        if forced_move.is_none() && is_win(update_pieces!(state.opp_pieces, next_move), WIN_LENGTH) {
+            forced_move = Some(col);
+        }
+    }
+
+    if forced_move.is_some() {
+        return Ok(forced_move);
+    }
+
+    for (col, next_move) in next_legal_moves(DefaultBoard, caches.move_order(), state.height_map) {
+        let child_eval = -evaluate_position_rec(
+            state.opp_pieces,
+            update_pieces!(state.curr_pieces, next_move),
+            update_height_map!(state.height_map, next_move),
+            state.moves_made + 1,
+            -eval,
+            -eval + 1,
+            FULL_DEPTH,
+            caches,
+            terminate,
+            &mut false,
+            pos
+        ).ok_or(Connect4Error::EvaluatePositionError)?;
+
+        if child_eval >= eval {
+            return Ok(Some(col));
+        }
+    }
+
+    Ok(None)
+}
+
+pub fn principal_variation(state: &State, eval: i8, caches: &mut StateCaches) -> Result<Vec<u32>> {
+    principal_variation_counted(state, eval, caches, &AtomicBool::new(false), &mut 0)
+}
+
+fn principal_variation_counted(
+    state: &State,
+    eval: i8,
+    caches: &mut StateCaches,
+    terminate: &AtomicBool,
+    pos: &mut usize,
+) -> Result<Vec<u32>> {
+
+    let mut principal_variation = Vec::new();
+    let mut curr_state = state.clone();
+    let mut curr_eval = eval;
+
+    while !curr_state.is_terminal() {
+        let Some(best_move) = principal_variation_move(
+            &curr_state, curr_eval, caches, terminate, pos)? else {
+            break;
+        };
+
+        principal_variation.push(best_move);
+        curr_state = curr_state.play_move(best_move);
+        curr_eval = -curr_eval;
+    }
+
+    Ok(principal_variation)
+}
+
+pub fn evaluate_position_pv(state: &State, caches: &mut StateCaches) -> Result<(SearchResult, Vec<u32>)> {
+    let mut nodes = 0;
+
+    let eval = evaluate_position_rec(
+        state.curr_pieces,
+        state.opp_pieces,
+        state.height_map,
+        state.moves_made,
+        MIN_EVAL,
+        MAX_EVAL,
+        FULL_DEPTH,
+        caches,
+        &AtomicBool::new(false),
+        &mut false,
+        &mut nodes,
+    ).ok_or(Connect4Error::EvaluatePositionError)?;
+
+    let principal_variation = principal_variation_counted(state, eval, caches, &AtomicBool::new(false), &mut nodes)?;
+
+    Ok((SearchResult { eval, nodes }, principal_variation))
+}
+
+pub fn solve(state: &State) -> Result<Solution> {
+    solve_until(state, &CancellationToken::new())
+}
+
+// solve, but giving up with EvaluatePositionError soon after cancel is cancelled, from any thread
+pub fn solve_until(state: &State, cancel: &CancellationToken) -> Result<Solution> {
+    let mut caches = StateCaches::new();
+
+    read_databases_into_caches(&mut caches)?;
+
+    let OptimalMoves { eval, best_moves, mut nodes } = optimal_moves_with_num_workers_until(
+        state, default_num_worker_threads(), &mut caches, cancel)?;
+    let principal_variation = principal_variation_counted(state, eval, &mut caches, cancel.flag(), &mut nodes)?;
+
+    Ok(Solution {
+        eval,
+        best_moves,
+        principal_variation,
+        nodes,
+    })
+}
+
+// the move to play, from the opening book when it has the position or else a full solve with the databases;
+// of the tied best moves the one earliest in DEFAULT_MOVE_ORDER, so the most central. None once the game is over
+pub fn best_move(state: &State) -> Result<Option<u32>> {
+    best_move_by(state, |best_moves| best_moves[0])
+}
+
+// best_move, but a pseudo-random one of the tied best moves, so an opponent doesn't play the same game every
+// time; the same seed always picks the same move
+pub fn best_move_with_seed(state: &State, seed: u64) -> Result<Option<u32>> {
+    // a multiplicative hash, so consecutive seeds don't pick consecutive moves
+    best_move_by(state, |best_moves| {
+        best_moves[((seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) % best_moves.len() as u64) as usize]
+    })
+}
+
+// uniformly one of the tied best moves, drawn from rng; only ever a move best_move could also have returned for
+// some tie break, so the variety costs no strength
+#[cfg(feature = "rand")]
+pub fn best_move_random(state: &State, rng: &mut impl rand::Rng) -> Result<Option<u32>> {
+    best_move_by(state, |best_moves| best_moves[rng.random_range(0..best_moves.len())])
+}
+
+// pick gets the tied best moves in DEFAULT_MOVE_ORDER, the book and the search each list them differently
+fn best_move_by(state: &State, pick: impl FnOnce(&[u32]) -> u32) -> Result<Option<u32>> {
+    if state.is_terminal() {
+        return Ok(None);
+    }
+
+    let best_moves = match lookup_book(state) {
+        Some(best_moves) => best_moves,
+        None => {
+            let mut caches = StateCaches::new();
+            read_databases_into_caches(&mut caches)?;
+
+            optimal_moves_with_workers(state, &mut caches)?.best_moves
+        },
+    };
+
+    Ok(Some(pick(&in_default_order(&best_moves))))
+}
+
+fn in_default_order(moves: &[u32]) -> Vec<u32> {
+    (0..COLS)
+        .map(|i| get_col!(DEFAULT_MOVE_ORDER, i))
+        .filter(|col| moves.contains(col))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    // how many plies ahead the engine looks before falling back on the heuristic eval, Hard solves the game
+    pub fn max_depth(self) -> i8 {
+        match self {
+            Difficulty::Easy => EASY_MAX_DEPTH,
+            Difficulty::Medium => MEDIUM_MAX_DEPTH,
+            Difficulty::Hard => FULL_DEPTH,
+        }
+    }
+}
+
+// best_move searching at most level.max_depth() plies, so below Hard the move is only the best the heuristic
+// eval can see and may well lose a won game; immediate wins and forced blocks are still always found
+pub fn best_move_at_level(state: &State, level: Difficulty) -> Result<Option<u32>> {
+    best_move_at_level_by(state, level, |best_moves| best_moves[0])
+}
+
+// best_move_at_level choosing among tied moves with rng, and at Easy playing any legal move one time in
+// EASY_RANDOM_MOVE_RATIO instead
+#[cfg(feature = "rand")]
+pub fn best_move_at_level_random(state: &State, level: Difficulty, rng: &mut impl rand::Rng) -> Result<Option<u32>> {
+    let legal_moves = state.legal_moves();
+
+    if level == Difficulty::Easy && !legal_moves.is_empty() && rng.random_ratio(1, EASY_RANDOM_MOVE_RATIO) {
+        return Ok(Some(legal_moves[rng.random_range(0..legal_moves.len())]));
+    }
+
+    best_move_at_level_by(state, level, |best_moves| best_moves[rng.random_range(0..best_moves.len())])
+}
+
+fn best_move_at_level_by(state: &State, level: Difficulty, pick: impl FnOnce(&[u32]) -> u32) -> Result<Option<u32>> {
+    if level == Difficulty::Hard {
+        return best_move_by(state, pick);
+    }
+
+    if state.is_terminal() {
+        return Ok(None);
+    }
+
+    let (_, best_moves) = iterative_deepening(state, level.max_depth())
+        .last()
+        .ok_or(Connect4Error::EvaluatePositionError)??;
+
+    Ok(Some(pick(&in_default_order(&best_moves))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // drawn with best play from columns 3, 4 and 1, while 2 and 6 lose on the last move
+    fn drawn_position() -> State {
+        State::from_moves(&[4, 4, 5, 1, 3, 2, 2, 1, 0, 3, 2, 1, 5, 5, 4, 6, 0, 6, 3, 5, 4, 4, 6, 5, 5, 6, 1, 1, 5, 2, 4])
+            .unwrap()
+    }
+
+    // the second query finds the first one's end game results in the reused caches
+    #[test]
+    fn reused_search_caches_give_the_same_eval_with_fewer_nodes() {
+        let state = drawn_position();
+        let caches = ReadOnlyCaches::new(StateCaches::new());
+        let mut search_caches = caches.search_caches();
+
+        let fresh = evaluate_position_read_only(&state, &caches).unwrap();
+        let first = evaluate_position_read_only_with(&state, &mut search_caches).unwrap();
+        let second = evaluate_position_read_only_with(&state, &mut search_caches).unwrap();
+
+        assert_eq!((first.eval, second.eval), (fresh.eval, fresh.eval));
+        assert_eq!(first.nodes, fresh.nodes);
+        assert!(second.nodes < first.nodes);
+    }
+
+    #[test]
+    fn contempt_outside_one_either_way_is_an_error() {
+        for contempt in [-2, 2, i8::MIN, i8::MAX] {
+            match optimal_moves_with_contempt(&State::start_state(), contempt) {
+                Err(Connect4Error::InvalidContempt(invalid)) => assert_eq!(invalid, contempt),
+                result => panic!("expected InvalidContempt({contempt}), got {result:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn a_window_without_room_between_alpha_and_beta_is_an_error() {
+        for (alpha, beta) in [(0, 0), (1, -1), (MAX_EVAL, MIN_EVAL)] {
+            match evaluate_window(&State::start_state(), alpha, beta) {
+                Err(Connect4Error::EmptyWindow { alpha: a, beta: b }) => assert_eq!((a, b), (alpha, beta)),
+                result => panic!("expected EmptyWindow for [{alpha}, {beta}], got {result:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn contempt_changes_the_move_played_in_a_drawn_position() {
+        let state = drawn_position();
+        let strict = optimal_moves_with_contempt(&state, 0).unwrap();
+        let avoiding_draws = optimal_moves_with_contempt(&state, 1).unwrap();
+        let seeking_draws = optimal_moves_with_contempt(&state, -1).unwrap();
+
+        assert_eq!((strict.eval, strict.best_moves.clone()), (0, vec![3, 4, 1]));
+        assert_eq!((seeking_draws.eval, seeking_draws.best_moves), (1, strict.best_moves.clone()));
+
+        // a draw ranks level with losing on the last move, so the engine's first choice becomes a losing one
+        assert_eq!(avoiding_draws.eval, -1);
+        assert_eq!(avoiding_draws.best_moves, [2, 6, 3, 4, 1]);
+        assert!(!strict.best_moves.contains(&avoiding_draws.best_moves[0]));
+    }
+
+    // a drawn game one to four moves from a full board, every one of them searched up to moves_made 49
+    #[test]
+    fn boards_nearly_full_solve_without_leaving_the_move_range() {
+        let full_draw = [
+            4, 2, 0, 5, 5, 5, 1, 1, 6, 4, 2, 6, 4, 5, 1, 3, 3, 1, 4, 3, 4, 3, 2, 5, 2,
+            4, 6, 2, 4, 2, 2, 1, 6, 3, 3, 0, 1, 0, 1, 3, 0, 6, 0, 0, 5, 6, 0, 6, 5,
+        ];
+
+        for moves_made in 45..MAX_TOTAL_MOVES as usize {
+            let state = State::from_moves(&full_draw[..moves_made]).unwrap();
+            let mut pos = 0;
+            let eval = evaluate_position_rec(
+                state.curr_pieces,
+                state.opp_pieces,
+                state.height_map,
+                state.moves_made,
+                MIN_EVAL,
+                MAX_EVAL,
+                FULL_DEPTH,
+                &mut StateCaches::new(),
+                &AtomicBool::new(false),
+                &mut false,
+                &mut pos,
+            );
+
+            assert_eq!(eval, Some(evaluate_position(&state).unwrap()));
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "moves_made 50 out of range")]
+    fn a_search_past_a_full_board_trips_the_move_range_check() {
+        let state = State::start_state();
+
+        evaluate_position_rec(
+            state.curr_pieces,
+            state.opp_pieces,
+            state.height_map,
+            MAX_TOTAL_MOVES + 1,
+            MIN_EVAL,
+            MAX_EVAL,
+            FULL_DEPTH,
+            &mut StateCaches::new(),
+            &AtomicBool::new(false),
+            &mut false,
+            &mut 0,
+        );
+    }
+
+    #[test]
+    fn iterative_deepening_reports_a_finished_game() {
+        let won = State::from_moves(&[3, 2, 3, 2, 3, 2, 3]).unwrap();
+        let mut results = iterative_deepening(&won, 10);
+
+        assert!(matches!(results.next(), Some(Err(Connect4Error::GameOver))));
+        assert!(results.next().is_none());
+    }
+
+    // the win in one is exact at the first depth, so there's nothing deeper to search
+    #[test]
+    fn iterative_deepening_stops_once_a_depth_is_exact() {
+        let before_win = State::from_moves(&[3, 2, 3, 2, 3, 2]).unwrap();
+        let results: Vec<(i8, Vec<u32>)> = iterative_deepening(&before_win, 10).collect::<Result<_>>().unwrap();
+
+        assert_eq!(results, [(max_eval!(6), vec![3])]);
+    }
+
+    #[test]
+    fn iterative_deepening_ends_on_the_solved_eval() {
+        let state = drawn_position();
+        let results: Vec<(i8, Vec<u32>)> = iterative_deepening(&state, MAX_TOTAL_MOVES).collect::<Result<_>>().unwrap();
+
+        assert!(results.len() > 1);
+        assert_eq!(results.last().unwrap().0, evaluate_position(&state).unwrap());
+    }
+
+    // checked against a separate brute force solver for 6x7 boards; on 7x7 the same moves lose, since the 7th row
+    // leaves room to finish a line
+    #[test]
+    fn standard_boards_solve_on_six_rows() {
+        let config = BoardConfig::STANDARD;
+        let drawn = State::from_moves_with(&config, &[
+            3, 4, 5, 5, 2, 3, 2, 0, 5, 5, 5, 0, 0, 3, 6, 5, 2, 0, 0, 2, 3, 3, 6, 0, 3, 6, 2, 4, 2, 6,
+        ]).unwrap();
+        let lost = State::from_moves_with(&config, &[
+            2, 1, 3, 5, 0, 0, 6, 4, 0, 2, 4, 0, 4, 1, 0, 0, 4, 4, 1, 2, 1, 5, 4, 1,
+        ]).unwrap();
+
+        assert_eq!(evaluate_position_on(&config, &drawn).unwrap(), DRAW);
+        assert_eq!(evaluate_position(&drawn).unwrap(), -5);
+        assert_eq!(evaluate_position_on(&config, &lost).unwrap(), -8);
+
+        let optimal = optimal_moves_on(&config, &drawn, &mut StateCaches::new()).unwrap();
+        assert_eq!((optimal.eval, optimal.best_moves), (DRAW, vec![4, 6]));
+    }
+
+    #[test]
+    fn boards_the_search_cannot_take_are_rejected() {
+        let state = State::from_moves(&[3, 3, 3, 3, 3, 3, 3]).unwrap();
+
+        // three in a row can be won a move sooner, an eval past MAX_EVAL
+        match evaluate_position_on(&DEFAULT_BOARD.with_win_len(3), &State::start_state()) {
+            Err(Connect4Error::UnsupportedBoard { win_len: 3, .. }) => {},
+            result => panic!("expected UnsupportedBoard, got {result:?}"),
+        }
+
+        match evaluate_position_on(&BoardConfig::STANDARD, &state) {
+            Err(Connect4Error::InconsistentState(_)) => {},
+            result => panic!("expected InconsistentState, got {result:?}"),
+        }
+    }
+
+    // checked against a separate brute force solver for five in a row; the won position already has four in a row,
+    // which would have ended a game of Connect 4
+    #[test]
+    fn connect_five_solves_to_five_in_a_row() {
+        let config = DEFAULT_BOARD.with_win_len(5);
+        let won = State::from_moves_with(&config, &[
+            2, 4, 3, 5, 0, 5, 4, 1, 0, 3, 6, 6, 3, 4, 4, 5, 5, 5, 5, 3, 6, 6, 3, 3, 3, 6, 6, 1, 2, 4, 0, 2, 0,
+        ]).unwrap();
+        let lost = State::from_moves_with(&config, &[
+            5, 0, 5, 5, 5, 3, 1, 4, 6, 4, 4, 5, 3, 2, 4, 4, 6, 6, 5, 2, 5, 0, 1, 1, 4, 3, 4, 1, 1, 1, 0, 3, 1,
+        ]).unwrap();
+
+        assert_eq!(config.max_eval(), MAX_EVAL - 1);
+        assert!(won.is_terminal() && !won.is_terminal_with(&config));
+        assert_eq!(evaluate_position_on(&config, &won).unwrap(), 3);
+        assert_eq!(evaluate_position_on(&config, &lost).unwrap(), -1);
+
+        let optimal = optimal_moves_on(&config, &won, &mut StateCaches::new()).unwrap();
+        assert_eq!((optimal.eval, optimal.best_moves), (3, vec![0]));
+    }
+}
@@ -1,3 +1,5 @@
+use alloc::string::String;
+#[cfg(feature = "std")]
 use std::io;
 use thiserror::Error;
 
@@ -8,11 +10,45 @@ pub enum Connect4Error {
     #[error("Failed to evaluate position")]
     EvaluatePositionError,
 
-    #[error("Worker thread join error")]
-    WorkerThreadJoinError,
+    #[error("Worker thread panicked: {0}")]
+    WorkerThreadPanic(String),
 
+    #[cfg(feature = "std")]
     #[error("{0}")]
-    DatabaseIOError(#[from] io::Error)
+    DatabaseIOError(#[from] io::Error),
+
+    #[error("Database version mismatch: {0}")]
+    DatabaseVersionMismatch(String),
+
+    #[error("Illegal move in column {col}")]
+    IllegalMove { col: u32 },
+
+    #[error("Illegal move in column {col} at index {index}")]
+    IllegalMoveAt { index: usize, col: u32 },
+
+    #[error("Invalid notation: {0}")]
+    InvalidNotation(String),
+
+    #[error("Invalid board: {0}")]
+    InvalidBoard(String),
+
+    #[error("Invalid bitboard {bitboard:#x}: {reason}")]
+    InvalidBitboard { bitboard: u64, reason: String },
+
+    #[error("Inconsistent state: {0}")]
+    InconsistentState(String),
+
+    #[error("Contempt {0} outside [-1, 1]")]
+    InvalidContempt(i8),
+
+    #[error("Empty window [{alpha}, {beta}]")]
+    EmptyWindow { alpha: i8, beta: i8 },
+
+    #[error("The game is already over")]
+    GameOver,
+
+    #[error("Unsupported board: {rows}x{cols} connecting {win_len}")]
+    UnsupportedBoard { rows: u32, cols: u32, win_len: u32 },
 }
 
-pub type Result<T> = std::result::Result<T, Connect4Error>;
+pub type Result<T> = core::result::Result<T, Connect4Error>;
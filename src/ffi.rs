@@ -0,0 +1,94 @@
+// the C ABI declared in include/connect4.h, where the contract for every pointer is spelled out
+#![allow(clippy::missing_safety_doc)]
+
+use crate::engine::best_move;
+use crate::state::{Outcome, Player, State};
+use std::ffi::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+
+pub const C4_OK: c_int = 0;
+pub const C4_ILLEGAL_MOVE: c_int = 1;
+pub const C4_PANIC: c_int = -2;
+
+pub const C4_NO_MOVE: c_int = -1;
+
+pub const C4_ONGOING: c_int = 0;
+pub const C4_X_WON: c_int = 1;
+pub const C4_O_WON: c_int = 2;
+pub const C4_DRAW: c_int = 3;
+
+
+// unwinding out of an extern "C" function aborts the whole program, so every function stops a panic here and
+// returns C4_PANIC for it instead
+fn catch_panic(f: impl FnOnce() -> c_int) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(C4_PANIC)
+}
+
+// the empty board with X to move, owned by the caller until it's given back to c4_free; null if it panicked
+#[unsafe(no_mangle)]
+pub extern "C" fn c4_new() -> *mut State {
+    panic::catch_unwind(|| Box::into_raw(Box::new(State::start_state()))).unwrap_or(ptr::null_mut())
+}
+
+// frees a state from c4_new, a null pointer is ignored like free ignores one
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn c4_free(state: *mut State) {
+    if !state.is_null() {
+        // nothing to report a panic to, the state is gone either way
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(state) })));
+    }
+}
+
+// a full column, a column off the board and any move once the game is over are all illegal, and leave the
+// state as it was
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn c4_play(state: *mut State, col: c_int) -> c_int {
+    let state = unsafe { &mut *state };
+
+    catch_panic(|| match u32::try_from(col).map(|col| state.try_play_move(col)) {
+        Ok(Ok(next_state)) => {
+            *state = next_state;
+            C4_OK
+        },
+        _ => C4_ILLEGAL_MOVE,
+    })
+}
+
+// best_move, so a full solve with the databases in the working directory when the book doesn't have the
+// position; C4_NO_MOVE once the game is over or if the search fails
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn c4_best_move(state: *const State) -> c_int {
+    let state = unsafe { &*state };
+
+    catch_panic(|| match best_move(state) {
+        Ok(Some(col)) => col as c_int,
+        _ => C4_NO_MOVE,
+    })
+}
+
+// which player won rather than whether it was the side to move, which a caller would have to track itself
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn c4_outcome(state: *const State) -> c_int {
+    let state = unsafe { &*state };
+
+    catch_panic(|| match (state.outcome(), state.side_to_move()) {
+        (None, _) => C4_ONGOING,
+        (Some(Outcome::Draw), _) => C4_DRAW,
+        (Some(Outcome::CurrentPlayerWon), Player::X) | (Some(Outcome::OpponentWon), Player::O) => C4_X_WON,
+        (Some(Outcome::CurrentPlayerWon), Player::O) | (Some(Outcome::OpponentWon), Player::X) => C4_O_WON,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_panic_becomes_c4_panic() {
+        assert_eq!(catch_panic(|| panic!("search failed")), C4_PANIC);
+        assert_eq!(catch_panic(|| C4_OK), C4_OK);
+    }
+}
+
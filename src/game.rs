@@ -0,0 +1,141 @@
+use crate::book::lookup_book;
+use crate::caches::StateCaches;
+use crate::engine::optimal_moves;
+use crate::error::Result;
+use crate::state::{Outcome, State, COLS};
+use std::io;
+use std::io::{BufRead, Write};
+
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Game {
+    current: State,
+    history: Vec<u32>,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Game {
+    pub fn new() -> Self {
+        Self {
+            current: State::start_state(),
+            history: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, col: u32) -> Result<()> {
+        self.current = self.current.try_play_move(col)?;
+        self.history.push(col);
+
+        Ok(())
+    }
+
+    // a piece can't be lifted out of the bitboards cheaply (the height sentinels), so undo replays
+    // the remaining history from the start state and costs O(n) in the number of moves played
+    pub fn undo(&mut self) -> Option<u32> {
+        let col = self.history.pop()?;
+
+        self.current = self.history.iter()
+            .fold(State::start_state(), |state, &col| state.play_move(col));
+
+        Some(col)
+    }
+
+    pub fn current_state(&self) -> &State {
+        &self.current
+    }
+
+    pub fn history(&self) -> &[u32] {
+        &self.history
+    }
+}
+
+// the engine answers from the opening book when it can, otherwise it searches the position
+fn engine_move(state: &State, caches: &mut StateCaches) -> Result<u32> {
+    if let Some(book_moves) = lookup_book(state) {
+        return Ok(book_moves[0]);
+    }
+
+    Ok(optimal_moves(state, caches)?.best_moves[0])
+}
+
+// colored only when built with the color feature, writing to a terminal and NO_COLOR isn't set
+#[cfg(feature = "color")]
+fn render_board(state: &State) -> String {
+    use std::io::IsTerminal;
+
+    if std::env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal() {
+        state.render_ansi()
+    } else {
+        state.pretty()
+    }
+}
+
+#[cfg(not(feature = "color"))]
+fn render_board(state: &State) -> String {
+    state.pretty()
+}
+
+fn read_human_move(game: &Game, input: &mut impl BufRead, output: &mut impl Write) -> Result<Option<u32>> {
+    loop {
+        write!(output, "Column (0-{}): ", COLS - 1)?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        match line.trim().parse::<u32>() {
+            Ok(col) if col >= COLS => writeln!(output, "Column {col} is out of range")?,
+            Ok(col) if !game.current_state().is_legal(col) => writeln!(output, "Column {col} is full")?,
+            Ok(col) => return Ok(Some(col)),
+            Err(_) => writeln!(output, "Enter a column number")?,
+        }
+    }
+}
+
+pub fn play_interactive() -> Result<()> {
+    play_interactive_with(&mut io::stdin().lock(), &mut io::stdout())
+}
+
+// the human moves first, the game stops early if the input runs out
+pub fn play_interactive_with(input: &mut impl BufRead, output: &mut impl Write) -> Result<()> {
+    let mut game = Game::new();
+    let mut caches = StateCaches::new();
+
+    writeln!(output, "{}", render_board(game.current_state()))?;
+
+    while game.current_state().outcome().is_none() {
+        let human_to_move = (game.history().len() & 1) == 0;
+
+        let col = if human_to_move {
+            match read_human_move(&game, input, output)? {
+                Some(col) => col,
+                None => return Ok(()),
+            }
+        } else {
+            let col = engine_move(game.current_state(), &mut caches)?;
+            writeln!(output, "Engine plays {col}")?;
+            col
+        };
+
+        game.push(col)?;
+        writeln!(output, "{}", render_board(game.current_state()))?;
+    }
+
+    // whoever made the last move is the opponent of the side to move
+    let human_moved_last = (game.history().len() & 1) == 1;
+
+    match game.current_state().outcome() {
+        Some(Outcome::OpponentWon) if human_moved_last => writeln!(output, "You win")?,
+        Some(Outcome::OpponentWon) => writeln!(output, "Engine wins")?,
+        _ => writeln!(output, "Draw")?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod engine;
+pub mod threats;
+pub mod state;
+#[cfg(feature = "std")]
+pub mod caches;
+#[cfg(feature = "std")]
+pub mod worker_threads;
+#[cfg(feature = "std")]
+pub mod cancellation;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod database;
+#[cfg(feature = "std")]
+pub mod game;
+#[cfg(feature = "std")]
+pub mod book;
+#[cfg(feature = "tokio")]
+pub mod async_solve;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "std")]
+pub use engine::{solve, Solution};
+#[cfg(feature = "tokio")]
+pub use async_solve::solve_async;
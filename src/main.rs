@@ -1,24 +1,120 @@
-use crate::database::generate_database;
-use crate::error::Result;
-use crate::state::State;
+use connect4::database::{generate_database_into, DEFAULT_CHECKPOINT_INTERVAL};
+use connect4::engine::moves_to_win;
+use connect4::error::Result;
+use connect4::game::play_interactive;
+use connect4::solve;
+use connect4::state::State;
+use connect4::worker_threads::default_num_worker_threads;
+use std::path::Path;
+use std::process::ExitCode;
 use std::time::Instant;
-use crate::worker_threads::DEFAULT_NUM_WORKER_THREADS;
+use std::{env, fs};
 
-mod engine;
-mod threats;
-mod state;
-mod caches;
-mod worker_threads;
-mod error;
-mod database;
 
-fn main() -> Result<()> {
+const USAGE: &str = "\
+usage: connect4 <command> [options]
+
+commands:
+    solve (--position <notation> | --file <rows>)    print the eval and best moves of a position
+    play                                              play against the engine, columns are read from stdin
+    gen-db [--depth N] [--workers M] [--out <dir>]    build the opening databases, flushing them every
+           [--checkpoint K]                           K solved states so a killed build can resume";
+
+const DEFAULT_DATABASE_DEPTH: usize = 4;
+
+
+fn option_value<'a>(options: &'a [String], name: &str) -> Option<&'a str> {
+    options.iter()
+        .position(|option| option == name)
+        .and_then(|i| options.get(i + 1))
+        .map(String::as_str)
+}
+
+fn parse_option<T: std::str::FromStr>(options: &[String], name: &str, default: T) -> std::result::Result<T, String> {
+    match option_value(options, name) {
+        Some(value) => value.parse().map_err(|_| format!("invalid value {value:?} for {name}")),
+        None => Ok(default),
+    }
+}
+
+fn read_position(options: &[String]) -> std::result::Result<State, String> {
+    if let Some(notation) = option_value(options, "--position") {
+        State::from_notation(notation).map_err(|err| err.to_string())
+    } else if let Some(filename) = option_value(options, "--file") {
+        let board = fs::read_to_string(filename).map_err(|err| format!("{filename}: {err}"))?;
+        let rows: Vec<&str> = board.lines().collect();
+
+        State::try_encode(&rows).map_err(|err| err.to_string())
+    } else {
+        Err("solve needs --position or --file".to_string())
+    }
+}
+
+fn run_solve(state: &State) -> Result<()> {
+    let time = Instant::now();
+    let solution = solve(state)?;
+
+    println!("{state}");
+    println!("Eval: {}", solution.eval);
+
+    if let Some(plies) = moves_to_win(solution.eval, state.moves_made) {
+        println!("Moves To Win: {plies}");
+    }
+
+    println!("Best Moves: {:?}", solution.best_moves);
+    println!("Principal Variation: {:?}", solution.principal_variation);
+    println!("Pos: {}", solution.nodes);
+    println!("Time: {:?}", time.elapsed());
+
+    Ok(())
+}
+
+fn run_gen_db(dir: &Path, depth: usize, num_workers: usize, checkpoint_interval: usize) -> Result<()> {
     let time = Instant::now();
 
-    let pos = generate_database(4, DEFAULT_NUM_WORKER_THREADS)?;
+    let pos = generate_database_into(dir, depth, num_workers, checkpoint_interval)?;
 
     println!("Pos: {pos}");
     println!("Time: {:?}", time.elapsed());
 
     Ok(())
 }
+
+fn run(args: &[String]) -> std::result::Result<Result<()>, String> {
+    let (command, options) = args.split_first().ok_or("missing command")?;
+
+    match command.as_str() {
+        "solve" => Ok(run_solve(&read_position(options)?)),
+        "play" => Ok(play_interactive()),
+        "gen-db" => {
+            let depth = parse_option(options, "--depth", DEFAULT_DATABASE_DEPTH)?;
+            let num_workers = parse_option(options, "--workers", default_num_worker_threads())?;
+            let checkpoint_interval = parse_option(options, "--checkpoint", DEFAULT_CHECKPOINT_INTERVAL)?;
+
+            // without --out the databases go in the working directory
+            let dir = option_value(options, "--out").unwrap_or("");
+            if !dir.is_empty() {
+                fs::create_dir_all(dir).map_err(|err| format!("{dir}: {err}"))?;
+            }
+
+            Ok(run_gen_db(Path::new(dir), depth, num_workers, checkpoint_interval))
+        },
+        _ => Err(format!("unknown command {command:?}")),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match run(&args) {
+        Ok(Ok(())) => ExitCode::SUCCESS,
+        Ok(Err(err)) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        },
+        Err(usage_err) => {
+            eprintln!("{usage_err}\n\n{USAGE}");
+            ExitCode::from(2)
+        },
+    }
+}
@@ -1,21 +1,70 @@
-use std::cmp::min;
-use crate::engine::{is_win, DEFAULT_MOVE_ORDER, IS_LEGAL};
+use core::cmp::min;
+use crate::engine::{is_win, is_win_with, DEFAULT_MOVE_ORDER, IS_LEGAL, MAX_TOTAL_MOVES, WIN_LENGTH};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+#[cfg(feature = "std")]
 use std::collections::HashSet;
-use std::fmt;
 use crate::threats::FOUR_BIT_MASK;
-use crate::{col_shift, index, open_row, update_height_map, update_pieces};
+use crate::error::{Connect4Error, Result};
+use crate::index;
 
 
-pub const ROWS: u32 = 7;
-pub const COLS: u32 = 7;
-pub const BOARD_BITS: usize = 56;
-pub const BOARD_MASK: u64 = (1 << BOARD_BITS) - 1;
 pub const COL_BITS: usize = 8;
 pub const COL_MASK: u64 = (1 << COL_BITS) - 1;
+pub const DEFAULT_BOARD: BoardConfig = BoardConfig::SQUARE;
+pub const ROWS: u32 = DEFAULT_BOARD.rows;
+pub const COLS: u32 = DEFAULT_BOARD.cols;
+pub const BOARD_BITS: usize = DEFAULT_BOARD.board_bits();
+pub const BOARD_MASK: u64 = DEFAULT_BOARD.board_mask();
 
 
+// every column keeps COL_BITS bits so the sentinel above a full column never touches the next one,
+// which limits boards to fewer than COL_BITS rows and at most 64 / COL_BITS columns. The functions that take a
+// config (the _with ones here, and the engine's _on ones) play, check and solve positions on it; the rest, and the
+// threaded searches, databases, opening book, pretty and to_notation, are all on DEFAULT_BOARD
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BoardConfig {
+    pub rows: u32,
+    pub cols: u32,
+    pub win_len: u32,
+}
+
+// the board a search runs on: DefaultBoard is DEFAULT_BOARD known at compile time, so the default search keeps
+// its masks and bounds as constants, and a BoardConfig is one picked at runtime
+pub trait Board: Copy {
+    fn config(self) -> BoardConfig;
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct DefaultBoard;
+
+
+// relative to the side to move: after play_move the player who just moved becomes the opponent,
+// so in positions reached by play only OpponentWon can occur
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Outcome {
+    CurrentPlayerWon,
+    OpponentWon,
+    Draw,
+}
+
+
+// the players as encode, decode, to_notation and pretty draw them, X always moves first
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Player {
+    X,
+    O,
+}
+
+
+// with serde the four fields serialize as they are; board_string gives the board as decode draws it instead
 #[derive(Debug)]
 #[derive(Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct State {
     pub curr_pieces: u64,
     pub opp_pieces: u64,
@@ -59,24 +108,115 @@ macro_rules! open_row {
     };
 }
 
+impl BoardConfig {
+    pub const STANDARD: Self = Self::new(6, 7);
+    pub const SQUARE: Self = Self::new(7, 7);
+
+    pub const DEFAULT_WIN_LEN: u32 = 4;
+
+    pub const fn new(rows: u32, cols: u32) -> Self {
+        assert!(rows > 0 && rows < COL_BITS as u32);
+        assert!(cols > 0 && cols as usize * COL_BITS <= u64::BITS as usize);
+
+        Self { rows, cols, win_len: Self::DEFAULT_WIN_LEN }
+    }
+
+    pub const fn with_win_len(self, win_len: u32) -> Self {
+        assert!(win_len > 1 && (win_len <= self.rows || win_len <= self.cols));
+
+        Self { win_len, ..self }
+    }
+
+    pub const fn board_bits(&self) -> usize {
+        self.cols as usize * COL_BITS
+    }
+
+    pub const fn board_mask(&self) -> u64 {
+        if self.board_bits() == u64::BITS as usize {
+            u64::MAX
+        } else {
+            (1 << self.board_bits()) - 1
+        }
+    }
+
+    // every row of every column, the board mask over COL_MASK being a 1 at the bottom of each column
+    pub const fn is_legal_mask(&self) -> u64 {
+        ((1 << self.rows) - 1) * (self.board_mask() / COL_MASK)
+    }
+
+    pub const fn max_total_moves(&self) -> i8 {
+        (self.rows * self.cols) as i8
+    }
+
+    pub const fn max_player_moves(&self) -> i8 {
+        (self.max_total_moves() + 1) / 2
+    }
+
+    // the eval of the earliest possible win, on move win_len of a player, and so the widest window a search needs
+    pub const fn max_eval(&self) -> i8 {
+        self.max_player_moves() - (self.win_len as i8 - 1)
+    }
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        DEFAULT_BOARD
+    }
+}
+
+impl Board for BoardConfig {
+    #[inline(always)]
+    fn config(self) -> BoardConfig {
+        self
+    }
+}
+
+impl Board for DefaultBoard {
+    #[inline(always)]
+    fn config(self) -> BoardConfig {
+        DEFAULT_BOARD
+    }
+}
+
 fn reflect_bitboard(state: u64) -> u64 {
+    reflect_bitboard_with(&DEFAULT_BOARD, state)
+}
+
+pub fn reflect_bitboard_with(config: &BoardConfig, state: u64) -> u64 {
+    let board_bits = config.board_bits();
     let mut reflected = 0;
 
-    for i in (0..BOARD_BITS).step_by(COL_BITS) {
-        reflected |= ((state >> i) & COL_MASK) << ((BOARD_BITS - COL_BITS) - i);
+    for i in (0..board_bits).step_by(COL_BITS) {
+        reflected |= ((state >> i) & COL_MASK) << ((board_bits - COL_BITS) - i);
     }
 
     reflected
 }
 
+// the cache key for a position: the side to move's pieces plus the height map pin down the whole board, and
+// the smaller of it and its mirror image is kept so both reflections share one entry; the colors can't be
+// swapped as well, since the side to move is part of what the eval means
 pub fn state_bitboard(curr_pieces: u64, height_map: u64) -> u64 {
+    state_bitboard_on(DefaultBoard, curr_pieces, height_map)
+}
+
+pub fn state_bitboard_on<B: Board>(board: B, curr_pieces: u64, height_map: u64) -> u64 {
     let bitboard = curr_pieces | height_map;
-    let reflected_bitboard = reflect_bitboard(bitboard);
+    let reflected_bitboard = reflect_bitboard_with(&board.config(), bitboard);
 
     min(bitboard, reflected_bitboard)
 }
 
 
+impl Player {
+    pub fn symbol(self) -> char {
+        match self {
+            Player::X => State::CURR_PIECE,
+            Player::O => State::OPP_PIECE,
+        }
+    }
+}
+
 impl State {
     const CURR_PIECE: char = 'X';
     const OPP_PIECE: char = 'O';
@@ -90,10 +230,19 @@ impl State {
         }
     }
 
+    // to_bitboard keeps whichever of a position and its mirror image encodes smaller, so the round trip
+    // from_bitboard(state.to_bitboard()) gives back either state or state.mirror(): always canonical_eq
+    // to state, with the same eval, but not necessarily == to it
     pub fn from_bitboard(bitboard: u64) -> State {
+        Self::from_bitboard_with(&DEFAULT_BOARD, bitboard)
+    }
+
+    // panics on a column without a height sentinel, bitboards from anywhere but to_bitboard should go through
+    // try_from_bitboard_with
+    pub fn from_bitboard_with(config: &BoardConfig, bitboard: u64) -> State {
         let mut state = Self::allocate();
 
-        for i in 0..COLS {
+        for i in 0..config.cols {
             let col_bits = (bitboard >> col_shift!(i)) & COL_MASK;
             let height = col_bits.ilog2();
             let curr_col_mask = (1 << height) - 1;
@@ -107,16 +256,199 @@ impl State {
         state
     }
 
+    pub fn from_parts(curr_pieces: u64, opp_pieces: u64, height_map: u64, moves_made: i8) -> Result<State> {
+        Self::from_parts_with(&DEFAULT_BOARD, curr_pieces, opp_pieces, height_map, moves_made)
+    }
+
+    // the checked way to build a state from its fields: the pieces can't overlap or float, every column's
+    // sentinel sits right on top of its pieces, and moves_made and the side to move agree with the piece counts;
+    // whether the position could come up in a real game isn't checked, see is_reachable
+    pub fn from_parts_with(
+        config: &BoardConfig,
+        curr_pieces: u64,
+        opp_pieces: u64,
+        height_map: u64,
+        moves_made: i8
+    ) -> Result<State> {
+        let inconsistent = |reason: String| Err(Connect4Error::InconsistentState(reason));
+        let pieces = curr_pieces | opp_pieces;
+
+        if (curr_pieces & opp_pieces) != 0 {
+            return inconsistent("the two sides' pieces overlap".to_string());
+        }
+
+        if (pieces & !config.is_legal_mask()) != 0 {
+            return inconsistent("pieces outside the board".to_string());
+        }
+
+        if (height_map & !config.board_mask()) != 0 {
+            return inconsistent(format!("height map bits past column {}", config.cols - 1));
+        }
+
+        for c in 0..config.cols {
+            let col_pieces = (pieces >> col_shift!(c)) & COL_MASK;
+            let col_height = col_pieces.count_ones();
+
+            if col_pieces != (1 << col_height) - 1 {
+                return inconsistent(format!("piece floating above an empty cell in column {c}"));
+            }
+
+            if (height_map >> col_shift!(c)) & COL_MASK != 1 << col_height {
+                return inconsistent(format!("height map doesn't sit on top of the {col_height} pieces in column {c}"));
+            }
+        }
+
+        if moves_made as u32 != pieces.count_ones() {
+            return inconsistent(format!("{moves_made} moves made but {} pieces on the board", pieces.count_ones()));
+        }
+
+        // the side to move has had as many moves as the opponent, or one fewer
+        if opp_pieces.count_ones() != curr_pieces.count_ones() + (moves_made & 1) as u32 {
+            return inconsistent(format!(
+                "{} pieces for the side to move and {} for the opponent after {moves_made} moves",
+                curr_pieces.count_ones(), opp_pieces.count_ones()));
+        }
+
+        Ok(State { curr_pieces, opp_pieces, height_map, moves_made })
+    }
+
+    // from_bitboard trusts its input, bitboards read from disk should go through try_from_bitboard
+    pub fn try_from_bitboard(bitboard: u64) -> Result<State> {
+        Self::try_from_bitboard_with(&DEFAULT_BOARD, bitboard)
+    }
+
+    // every column needs its height sentinel at or below the top row, and nothing may be set past the last column
+    pub fn try_from_bitboard_with(config: &BoardConfig, bitboard: u64) -> Result<State> {
+        let invalid = |reason: String| Connect4Error::InvalidBitboard { bitboard, reason };
+
+        if (bitboard & !config.board_mask()) != 0 {
+            return Err(invalid(format!("bits set past column {}", config.cols - 1)));
+        }
+
+        for i in 0..config.cols {
+            let col_bits = (bitboard >> col_shift!(i)) & COL_MASK;
+
+            if col_bits == 0 {
+                return Err(invalid(format!("column {i} has no height sentinel")));
+            }
+
+            if col_bits.ilog2() > config.rows {
+                return Err(invalid(format!("column {i} is higher than {} rows", config.rows)));
+            }
+        }
+
+        Ok(Self::from_bitboard_with(config, bitboard))
+    }
+
     pub fn to_bitboard(&self) -> u64 {
         state_bitboard(self.curr_pieces, self.height_map)
     }
 
+    // the same position with the columns in reverse order, which always has the same eval
+    pub fn mirror(&self) -> Self {
+        Self {
+            curr_pieces: reflect_bitboard(self.curr_pieces),
+            opp_pieces: reflect_bitboard(self.opp_pieces),
+            height_map: reflect_bitboard(self.height_map),
+            moves_made: self.moves_made,
+        }
+    }
+
+    pub fn canonical_eq(&self, other: &Self) -> bool {
+        self.to_bitboard() == other.to_bitboard()
+    }
+
+    // the board reads the same left to right as right to left, as the empty board does; the side to move's pieces
+    // and the heights fix the opponent's, so those two being symmetric is enough
+    pub fn is_symmetric(&self) -> bool {
+        let bitboard = self.curr_pieces | self.height_map;
+
+        reflect_bitboard(bitboard) == bitboard
+    }
+
     pub fn start_state() -> Self {
-        Self::encode(vec![&" ".repeat(COLS as usize); ROWS as usize])
+        Self::start_state_with(&DEFAULT_BOARD)
+    }
+
+    // the same state encode_with gives for an empty board, no pieces and only the bottom row sentinels
+    pub fn start_state_with(config: &BoardConfig) -> Self {
+        let mut game_state = Self::allocate();
+
+        for c in 0..config.cols {
+            game_state.height_map |= 1 << col_shift!(c);
+        }
+
+        game_state
+    }
+
+    pub fn side_to_move(&self) -> Player {
+        if (self.moves_made & 1) == 0 { Player::X } else { Player::O }
+    }
+
+    // plies played so far, so the move about to be made is ply() + 1
+    pub fn ply(&self) -> i8 {
+        self.moves_made
+    }
+
+    // moves left before the board fills up and the game is drawn
+    pub fn empty_squares(&self) -> u32 {
+        (MAX_TOTAL_MOVES - self.moves_made) as u32
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.moves_made == MAX_TOTAL_MOVES
+    }
+
+    // every occupied cell as (row, col, player) with row 0 at the bottom, a column at a time from the left and
+    // each column from the bottom up
+    pub fn cells(&self) -> impl Iterator<Item = (u32, u32, Player)> {
+        let (x_pieces, _) = self.x_and_o_pieces();
+        let mut pieces = self.curr_pieces | self.opp_pieces;
+
+        core::iter::from_fn(move || {
+            if pieces == 0 {
+                return None;
+            }
+
+            let cell = pieces.trailing_zeros();
+            pieces &= pieces - 1;
+
+            let player = if (x_pieces >> cell) & 1 != 0 { Player::X } else { Player::O };
+            Some((cell % COL_BITS as u32, cell / COL_BITS as u32, player))
+        })
+    }
+
+    // two planes stacked, 1.0 for a piece and 0.0 for an empty cell: rows 0 to ROWS - 1 hold the side to move's
+    // pieces and rows ROWS to 2 * ROWS - 1 the opponent's, with the bottom row first in each plane as in cells.
+    // Relative to the side to move like every eval, so a database entry's eval is the target for these as they are
+    pub fn to_planes(&self) -> [[f32; COLS as usize]; ROWS as usize * 2] {
+        let mut planes = [[0.0; COLS as usize]; ROWS as usize * 2];
+
+        for (plane, pieces) in [self.curr_pieces, self.opp_pieces].into_iter().enumerate() {
+            for row in 0..ROWS {
+                for col in 0..COLS {
+                    if (pieces >> (row + col_shift!(col))) & 1 != 0 {
+                        planes[plane * ROWS as usize + row as usize][col as usize] = 1.0;
+                    }
+                }
+            }
+        }
+
+        planes
+    }
+
+    // curr_pieces and opp_pieces belong to whoever is to move, this gives them back by player
+    fn x_and_o_pieces(&self) -> (u64, u64) {
+        match self.side_to_move() {
+            Player::X => (self.curr_pieces, self.opp_pieces),
+            Player::O => (self.opp_pieces, self.curr_pieces),
+        }
     }
 
     pub fn play_move(&self, col: u32) -> Self {
         let next_move = open_row!(self.height_map, col);
+        debug_assert!(col < COLS && (next_move & IS_LEGAL) != 0, "illegal move in column {col}");
+        debug_assert!(!self.is_full(), "move played on a full board");
 
         Self {
             curr_pieces: self.opp_pieces,
@@ -126,16 +458,139 @@ impl State {
         }
     }
 
+    pub fn is_terminal(&self) -> bool {
+        self.outcome().is_some()
+    }
+
+    pub fn is_terminal_with(&self, config: &BoardConfig) -> bool {
+        self.outcome_with(config).is_some()
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        if is_win(self.opp_pieces, WIN_LENGTH) {
+            Some(Outcome::OpponentWon)
+        } else if is_win(self.curr_pieces, WIN_LENGTH) {
+            Some(Outcome::CurrentPlayerWon)
+        } else if self.is_full() {
+            Some(Outcome::Draw)
+        } else {
+            None
+        }
+    }
+
+    pub fn outcome_with(&self, config: &BoardConfig) -> Option<Outcome> {
+        if is_win_with(config, self.opp_pieces) {
+            Some(Outcome::OpponentWon)
+        } else if is_win_with(config, self.curr_pieces) {
+            Some(Outcome::CurrentPlayerWon)
+        } else if self.moves_made == config.max_total_moves() {
+            Some(Outcome::Draw)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_reachable(&self) -> bool {
+        self.is_reachable_with(&DEFAULT_BOARD)
+    }
+
+    // the first player is never behind or more than one piece ahead, the player to move can't have won
+    // already, and a win by the opponent has to be completed by one of the pieces on top of a column
+    pub fn is_reachable_with(&self, config: &BoardConfig) -> bool {
+        let curr_count = self.curr_pieces.count_ones() as i8;
+        let opp_count = self.opp_pieces.count_ones() as i8;
+
+        if curr_count + opp_count != self.moves_made || opp_count - curr_count != (self.moves_made & 1) {
+            return false;
+        }
+
+        if is_win_with(config, self.curr_pieces) {
+            return false;
+        }
+
+        !is_win_with(config, self.opp_pieces) || (0..config.cols).any(|col| {
+            let last_move = open_row!(self.height_map, col) >> 1;
+
+            (self.opp_pieces & last_move) != 0 && !is_win_with(config, self.opp_pieces ^ last_move)
+        })
+    }
+
+    fn is_open(&self, col: u32) -> bool {
+        col < COLS && (open_row!(self.height_map, col) & IS_LEGAL) != 0
+    }
+
+    fn is_open_with(&self, config: &BoardConfig, col: u32) -> bool {
+        col < config.cols && (open_row!(self.height_map, col) & config.is_legal_mask()) != 0
+    }
+
+    pub fn is_legal(&self, col: u32) -> bool {
+        self.is_open(col) && !self.is_terminal()
+    }
+
+    pub fn is_legal_with(&self, config: &BoardConfig, col: u32) -> bool {
+        self.is_open_with(config, col) && !self.is_terminal_with(config)
+    }
+
+    pub fn legal_moves(&self) -> Vec<u32> {
+        if self.is_terminal() {
+            return vec![];
+        }
+
+        (0..COLS).filter(|&col| self.is_open(col)).collect()
+    }
+
+    pub fn legal_moves_with(&self, config: &BoardConfig) -> Vec<u32> {
+        if self.is_terminal_with(config) {
+            return vec![];
+        }
+
+        (0..config.cols).filter(|&col| self.is_open_with(config, col)).collect()
+    }
+
+    pub fn try_play_move(&self, col: u32) -> Result<Self> {
+        if !self.is_legal(col) {
+            return Err(Connect4Error::IllegalMove { col });
+        }
+
+        Ok(self.play_move(col))
+    }
+
+    // play_move itself works on any board, only which moves are legal depends on it
+    pub fn try_play_move_with(&self, config: &BoardConfig, col: u32) -> Result<Self> {
+        if !self.is_legal_with(config, col) {
+            return Err(Connect4Error::IllegalMove { col });
+        }
+
+        Ok(self.play_move(col))
+    }
+
+    pub fn from_moves(cols: &[u32]) -> Result<Self> {
+        cols.iter().enumerate().try_fold(Self::start_state(), |state, (index, &col)| {
+            state.try_play_move(col).map_err(|_| Connect4Error::IllegalMoveAt { index, col })
+        })
+    }
+
+    pub fn from_moves_with(config: &BoardConfig, cols: &[u32]) -> Result<Self> {
+        cols.iter().enumerate().try_fold(Self::start_state_with(config), |state, (index, &col)| {
+            state.try_play_move_with(config, col).map_err(|_| Connect4Error::IllegalMoveAt { index, col })
+        })
+    }
+
+    // encode trusts its input, boards from users should go through try_encode
     pub fn encode(board: Vec<&str>) -> Self {
+        Self::encode_with(&DEFAULT_BOARD, board)
+    }
+
+    pub fn encode_with(config: &BoardConfig, board: Vec<&str>) -> Self {
         let board_str = board.join("\n");
 
         let mut game_state = Self::allocate();
 
-        for c in 0..COLS {
-            let mut cell = 1 << (c * (ROWS + 1));
+        for c in 0..config.cols {
+            let mut cell = 1 << col_shift!(c);
 
-            for r in 0..ROWS {
-                let piece = board_str.as_bytes()[((ROWS - 1 - r) * (COLS + 1) + c) as usize] as char;
+            for r in 0..config.rows {
+                let piece = board_str.as_bytes()[((config.rows - 1 - r) * (config.cols + 1) + c) as usize] as char;
 
                 if piece == Self::CURR_PIECE {
                     game_state.curr_pieces |= cell;
@@ -154,43 +609,213 @@ impl State {
         }
 
         if (game_state.moves_made & 1) == 1 {
-            let temp = game_state.curr_pieces;
-            game_state.curr_pieces = game_state.opp_pieces;
-            game_state.opp_pieces = temp;
+            core::mem::swap(&mut game_state.curr_pieces, &mut game_state.opp_pieces);
         }
 
         game_state
     }
 
+    pub fn try_encode(board: &[&str]) -> Result<Self> {
+        Self::try_encode_with(&DEFAULT_BOARD, board)
+    }
+
+    pub fn try_encode_with(config: &BoardConfig, board: &[&str]) -> Result<Self> {
+        if board.len() != config.rows as usize {
+            return Err(Connect4Error::InvalidBoard(format!("expected {} rows, found {}", config.rows, board.len())));
+        }
+
+        let mut col_filled = vec![true; config.cols as usize];
+
+        // rows are given top to bottom, so walk them in reverse to spot pieces floating above an empty cell
+        for (r, row) in board.iter().enumerate().rev() {
+            let width = row.chars().count();
+            if width != config.cols as usize {
+                return Err(Connect4Error::InvalidBoard(format!("row {r} has width {width}, expected {}", config.cols)));
+            }
+
+            for (c, piece) in row.chars().enumerate() {
+                match piece {
+                    Self::CURR_PIECE | Self::OPP_PIECE if !col_filled[c] => {
+                        return Err(Connect4Error::InvalidBoard(format!("piece floating above an empty cell in column {c}")))
+                    },
+                    Self::CURR_PIECE | Self::OPP_PIECE => {},
+                    ' ' => col_filled[c] = false,
+                    _ => return Err(Connect4Error::InvalidBoard(format!("unknown piece {piece:?} in row {r}"))),
+                }
+            }
+        }
+
+        let state = Self::encode_with(config, board.to_vec());
+
+        if !state.is_reachable_with(config) {
+            return Err(Connect4Error::InvalidBoard("position can't be reached from the start position".to_string()));
+        }
+
+        Ok(state)
+    }
+
     pub fn decode(&self) -> String {
+        self.decode_with(&DEFAULT_BOARD)
+    }
+
+    // X is the first player as in encode, so encode(decode(state)) gives back the same state
+    pub fn decode_with(&self, config: &BoardConfig) -> String {
+        let (x_pieces, o_pieces) = self.x_and_o_pieces();
         let mut board_str = String::new();
 
-        for r in (0..ROWS).rev() {
-            let mut cell = 1 << r;
+        for r in (0..config.rows).rev() {
+            let mut cell: u64 = 1 << r;
 
-            for _ in 0..COLS {
-                if (self.curr_pieces & cell) != 0 {
+            for _ in 0..config.cols {
+                if (x_pieces & cell) != 0 {
                     board_str.push(Self::CURR_PIECE);
-                } else if (self.opp_pieces & cell) != 0 {
+                } else if (o_pieces & cell) != 0 {
                     board_str.push(Self::OPP_PIECE);
                 } else {
                     board_str.push(' ');
                 }
 
-                cell <<= ROWS + 1;
+                cell <<= COL_BITS;
+            }
+
+            board_str.push('\n');
+        }
+
+        board_str
+    }
+
+    // decode with column numbers above the board and whose turn it is below
+    pub fn pretty(&self) -> String {
+        self.render(&Self::CURR_PIECE.to_string(), &Self::OPP_PIECE.to_string())
+    }
+
+    // pretty with red X and yellow O pieces, for terminals; callers decide whether color is wanted
+    #[cfg(feature = "color")]
+    pub fn render_ansi(&self) -> String {
+        const RED: &str = "\x1b[31m";
+        const YELLOW: &str = "\x1b[33m";
+        const RESET: &str = "\x1b[0m";
+
+        self.render(&format!("{RED}{}{RESET}", Self::CURR_PIECE), &format!("{YELLOW}{}{RESET}", Self::OPP_PIECE))
+    }
+
+    fn render(&self, x_piece: &str, o_piece: &str) -> String {
+        let (x_pieces, o_pieces) = self.x_and_o_pieces();
+
+        let mut board_str: String = (0..COLS).map(|col| char::from_digit(col, 10).unwrap_or('?')).collect();
+        board_str.push('\n');
+        board_str.push_str(&"-".repeat(COLS as usize));
+        board_str.push('\n');
+
+        for r in (0..ROWS).rev() {
+            for col in 0..COLS {
+                let cell = 1 << (r + col_shift!(col));
+
+                if (x_pieces & cell) != 0 {
+                    board_str.push_str(x_piece);
+                } else if (o_pieces & cell) != 0 {
+                    board_str.push_str(o_piece);
+                } else {
+                    board_str.push(' ');
+                }
             }
 
             board_str.push('\n');
         }
 
+        board_str.push_str(&"-".repeat(COLS as usize));
+        board_str.push('\n');
+
+        board_str.push_str(&format!("{} to move\n", self.side_to_move().symbol()));
+
         board_str
     }
 
+    // columns left to right, each listed bottom to top, then the side to move, e.g. "///XO/// X";
+    // X is always the first player, as in encode
+    pub fn to_notation(&self) -> String {
+        let (x_pieces, o_pieces) = self.x_and_o_pieces();
+
+        let cols: Vec<String> = (0..COLS).map(|col| {
+            (0..ROWS).map_while(|row| {
+                let cell = 1 << (row + col_shift!(col));
+
+                if (x_pieces & cell) != 0 {
+                    Some(Self::CURR_PIECE)
+                } else if (o_pieces & cell) != 0 {
+                    Some(Self::OPP_PIECE)
+                } else {
+                    None
+                }
+            }).collect()
+        }).collect();
+
+        format!("{} {}", cols.join("/"), self.side_to_move().symbol())
+    }
+
+    pub fn from_notation(notation: &str) -> Result<Self> {
+        let invalid = |reason: &str| Connect4Error::InvalidNotation(format!("{reason} in {notation:?}"));
+
+        let (board, side_to_move) = notation.trim().rsplit_once(' ')
+            .ok_or_else(|| invalid("missing side to move"))?;
+
+        let cols: Vec<&str> = board.trim().split('/').collect();
+        if cols.len() != COLS as usize {
+            return Err(invalid("wrong number of columns"));
+        }
+
+        let mut x_pieces = 0;
+        let mut o_pieces = 0;
+        let mut state = Self::allocate();
+
+        for (col, pieces) in (0..COLS).zip(cols) {
+            if pieces.len() > ROWS as usize {
+                return Err(invalid("column overflow"));
+            }
+
+            let mut cell = 1 << col_shift!(col);
+
+            for piece in pieces.chars() {
+                match piece {
+                    Self::CURR_PIECE => x_pieces |= cell,
+                    Self::OPP_PIECE => o_pieces |= cell,
+                    _ => return Err(invalid("unknown piece")),
+                }
+
+                state.moves_made += 1;
+                cell <<= 1;
+            }
+
+            state.height_map |= cell;
+        }
+
+        if side_to_move != state.side_to_move().symbol().to_string() {
+            return Err(invalid("side to move does not match the piece count"));
+        }
+
+        (state.curr_pieces, state.opp_pieces) = if (state.moves_made & 1) == 0 {
+            (x_pieces, o_pieces)
+        } else {
+            (o_pieces, x_pieces)
+        };
+
+        // the same check try_encode makes, a piece count alone still lets one player get ahead or both win
+        if !state.is_reachable() {
+            return Err(invalid("position can't be reached from the start position"));
+        }
+
+        Ok(state)
+    }
+
     pub fn next_states(&self) -> Vec<Self> {
+        self.next_states_in_order(DEFAULT_MOVE_ORDER)
+    }
+
+    pub fn next_states_in_order(&self, move_order: u32) -> Vec<Self> {
         let mut next_states = vec![];
 
         for i in 0..COLS {
-            let col = (DEFAULT_MOVE_ORDER >> index!(i)) & FOUR_BIT_MASK;
+            let col = (move_order >> index!(i)) & FOUR_BIT_MASK;
             let next_move = open_row!(self.height_map, col);
 
             if (next_move & IS_LEGAL) != 0 {
@@ -205,12 +830,16 @@ impl State {
 
         next_states
     }
+}
 
+// the reachable state sets are HashSets, so they're only there with std
+#[cfg(feature = "std")]
+impl State {
     fn generate_states_rec(&self, depth: usize, states: &mut HashSet<u64>) {
         let state_bitboard = self.to_bitboard();
 
         if states.insert(state_bitboard) {
-            if depth == 0 || is_win(self.opp_pieces) {
+            if depth == 0 || is_win(self.opp_pieces, WIN_LENGTH) {
                 return;
             }
 
@@ -220,6 +849,8 @@ impl State {
         }
     }
 
+    // the set grows roughly COLS times per level of depth and nothing caps it, so a large depth can exhaust
+    // memory; generate_states_bounded gives up past a limit instead
     pub fn generate_states(&self, depth: usize) -> HashSet<u64> {
         let mut states = HashSet::new();
 
@@ -227,6 +858,82 @@ impl State {
 
         states
     }
+
+    // the same states as generate_states in the same depth first order, but generated only as they're pulled,
+    // so taking the first few costs the same at any depth
+    pub fn states_iter(&self, depth: usize) -> impl Iterator<Item = u64> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![(self.clone(), depth)];
+
+        core::iter::from_fn(move || {
+            while let Some((state, depth)) = stack.pop() {
+                let state_bitboard = state.to_bitboard();
+
+                if !seen.insert(state_bitboard) {
+                    continue;
+                }
+
+                if depth > 0 && !is_win(state.opp_pieces, WIN_LENGTH) {
+                    stack.extend(state.next_states().into_iter().rev().map(|next_state| (next_state, depth - 1)));
+                }
+
+                return Some(state_bitboard);
+            }
+
+            None
+        })
+    }
+
+    // returns false as soon as inserting another state would go past max_states
+    fn generate_states_bounded_rec(&self, depth: usize, max_states: usize, states: &mut HashSet<u64>) -> bool {
+        let state_bitboard = self.to_bitboard();
+
+        if states.contains(&state_bitboard) {
+            return true;
+        }
+
+        if states.len() == max_states {
+            return false;
+        }
+
+        states.insert(state_bitboard);
+
+        if depth == 0 || is_win(self.opp_pieces, WIN_LENGTH) {
+            return true;
+        }
+
+        self.next_states().iter().all(|next_state| next_state.generate_states_bounded_rec(depth - 1, max_states, states))
+    }
+
+    pub fn generate_states_bounded(&self, depth: usize, max_states: usize) -> Option<HashSet<u64>> {
+        let mut states = HashSet::new();
+
+        self.generate_states_bounded_rec(depth, max_states, &mut states).then_some(states)
+    }
+}
+
+// counts every move sequence of exactly depth plies, or shorter ones ending the game, so unlike generate_states
+// transpositions and mirror images are counted separately; for checking move generation against known counts
+pub fn perft(state: &State, depth: usize) -> u64 {
+    if depth == 0 || state.is_terminal() {
+        return 1;
+    }
+
+    state.next_states().iter().map(|next_state| perft(next_state, depth - 1)).sum()
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::start_state()
+    }
+}
+
+// hashes the canonical bitboard, so mirror images share a bucket; this stays consistent with the
+// field-wise Eq (equal states always hash equally), while canonical_eq is the symmetry-aware equality
+impl Hash for State {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_bitboard().hash(state);
+    }
 }
 
 impl fmt::Display for State {
@@ -234,3 +941,505 @@ impl fmt::Display for State {
         write!(f, "{}", self.decode())
     }
 }
+
+// for #[serde(with = "connect4::state::board_string")]: a state as its decode string, which reads back through
+// try_encode, so a hand edited board that can't be reached is an error rather than a bad state
+#[cfg(feature = "serde")]
+pub mod board_string {
+    use super::State;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(state: &State, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&state.decode())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> core::result::Result<State, D::Error> {
+        let board = String::deserialize(deserializer)?;
+
+        State::try_encode(&board.lines().collect::<Vec<_>>()).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // each choice picks one of the legal moves left, so every sequence plays a real game, stopping once it's over
+    fn play_out(choices: &[usize]) -> State {
+        let mut state = State::start_state();
+
+        for &choice in choices {
+            let legal_moves = state.legal_moves();
+
+            if legal_moves.is_empty() {
+                break;
+            }
+
+            state = state.play_move(legal_moves[choice % legal_moves.len()]);
+        }
+
+        state
+    }
+
+    fn reachable_state() -> impl Strategy<Value = State> {
+        prop::collection::vec(any::<usize>(), 0..=MAX_TOTAL_MOVES as usize).prop_map(|choices| play_out(&choices))
+    }
+
+    proptest! {
+        #[test]
+        fn encode_inverts_decode(state in reachable_state()) {
+            prop_assert_eq!(State::encode(state.decode().lines().collect()), state);
+        }
+
+        #[test]
+        fn from_bitboard_inverts_to_bitboard_up_to_mirroring(state in reachable_state()) {
+            let round_trip = State::from_bitboard(state.to_bitboard());
+
+            prop_assert!(round_trip == state || round_trip == state.mirror());
+            prop_assert!(round_trip.canonical_eq(&state));
+        }
+
+        #[test]
+        fn mirror_is_its_own_inverse(state in reachable_state()) {
+            prop_assert_eq!(state.mirror().mirror(), state.clone());
+            prop_assert_eq!(state.mirror().to_bitboard(), state.to_bitboard());
+        }
+
+        #[test]
+        fn mirror_reflects_every_bitboard_of_the_state(state in reachable_state()) {
+            let mirror = state.mirror();
+
+            prop_assert_eq!(mirror.curr_pieces, reflect_bitboard(state.curr_pieces));
+            prop_assert_eq!(mirror.opp_pieces, reflect_bitboard(state.opp_pieces));
+            prop_assert_eq!(mirror.height_map, reflect_bitboard(state.height_map));
+            prop_assert_eq!(mirror.moves_made, state.moves_made);
+        }
+
+        #[test]
+        fn symmetric_states_are_their_own_mirror(state in reachable_state()) {
+            prop_assert_eq!(state.is_symmetric(), state.mirror() == state);
+        }
+
+        #[test]
+        fn from_notation_inverts_to_notation(state in reachable_state()) {
+            prop_assert_eq!(State::from_notation(&state.to_notation()).unwrap(), state);
+        }
+
+        #[test]
+        fn played_positions_are_reachable(state in reachable_state()) {
+            prop_assert!(state.is_reachable());
+        }
+    }
+
+    // of a position and its mirror image only the one that encodes smaller comes back as itself
+    #[test]
+    fn from_bitboard_gives_back_the_canonical_one_of_a_mirrored_pair() {
+        let left = State::from_moves(&[0, 1, 0]).unwrap();
+        let right = State::from_moves(&[6, 5, 6]).unwrap();
+        let (canonical, reflected) = if left.to_bitboard() == left.curr_pieces | left.height_map {
+            (left, right)
+        } else {
+            (right, left)
+        };
+
+        assert_eq!(canonical.to_bitboard(), reflected.to_bitboard());
+        assert_eq!(State::from_bitboard(canonical.to_bitboard()), canonical);
+
+        let round_trip = State::from_bitboard(reflected.to_bitboard());
+        assert_ne!(round_trip, reflected);
+        assert_eq!(round_trip, reflected.mirror());
+        assert!(round_trip.canonical_eq(&reflected));
+        assert_eq!((round_trip.moves_made, round_trip.side_to_move()), (reflected.moves_made, reflected.side_to_move()));
+    }
+
+    #[test]
+    fn a_column_without_a_height_sentinel_is_an_invalid_bitboard() {
+        let bitboard = State::from_moves(&[3, 3, 2]).unwrap().to_bitboard();
+
+        for (zeroed, col) in [(bitboard & !(COL_MASK << col_shift!(3)), 3), (bitboard & !COL_MASK, 0), (0, 0)] {
+            match State::try_from_bitboard(zeroed) {
+                Err(Connect4Error::InvalidBitboard { bitboard, reason }) => {
+                    assert_eq!(bitboard, zeroed);
+                    assert_eq!(reason, format!("column {col} has no height sentinel"));
+                },
+                result => panic!("expected InvalidBitboard for {zeroed:#x}, got {result:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn from_notation_rejects_unreachable_positions() {
+        // a side to move that matches the piece count, but with X two pieces ahead or already the winner
+        for notation in ["XX////// X", "XXXX////// X", "XXXXOOO/O/O///// O", "XXXX/OOOO///// X"] {
+            match State::from_notation(notation) {
+                Err(Connect4Error::InvalidNotation(_)) => {},
+                result => panic!("expected InvalidNotation for {notation:?}, got {result:?}"),
+            }
+        }
+    }
+
+    // every square filled and no four in a row anywhere
+    const FULL_DRAW: [u32; MAX_TOTAL_MOVES as usize] = [
+        4, 2, 0, 5, 5, 5, 1, 1, 6, 4, 2, 6, 4, 5, 1, 3, 3, 1, 4, 3, 4, 3, 2, 5, 2,
+        4, 6, 2, 4, 2, 2, 1, 6, 3, 3, 0, 1, 0, 1, 3, 0, 6, 0, 0, 5, 6, 0, 6, 5,
+    ];
+
+    #[test]
+    fn a_full_board_without_four_in_a_row_is_a_draw() {
+        let drawn = State::from_moves(&FULL_DRAW).unwrap();
+
+        assert!(drawn.is_full());
+        assert_eq!(drawn.outcome(), Some(Outcome::Draw));
+        assert!(drawn.is_terminal());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "illegal move in column 0")]
+    fn playing_into_a_full_column_trips_the_debug_check() {
+        State::from_moves(&[0; 7]).unwrap().play_move(0);
+    }
+
+    #[test]
+    fn only_boards_that_read_the_same_mirrored_are_symmetric() {
+        let symmetric = State::from_moves(&[3, 3, 2, 1, 4, 5]).unwrap();
+        let asymmetric = State::from_moves(&[3, 3, 2, 4, 1, 1]).unwrap();
+        // the same cells filled on both sides, but by different players
+        let mirrored_colors = State::from_moves(&[2, 4]).unwrap();
+
+        assert!(State::start_state().is_symmetric());
+        assert!(symmetric.is_symmetric());
+        assert!(!asymmetric.is_symmetric());
+        assert!(!mirrored_colors.is_symmetric());
+
+        for state in [State::start_state(), symmetric, asymmetric, mirrored_colors] {
+            assert_eq!(state.is_symmetric(), state.mirror() == state);
+        }
+    }
+
+    #[test]
+    fn empty_squares_count_down_to_a_full_board() {
+        for moves_made in 0..=FULL_DRAW.len() {
+            let state = State::from_moves(&FULL_DRAW[..moves_made]).unwrap();
+
+            assert_eq!(state.empty_squares(), (FULL_DRAW.len() - moves_made) as u32);
+            assert_eq!(state.is_full(), moves_made == MAX_TOTAL_MOVES as usize);
+            assert_eq!(state.outcome() == Some(Outcome::Draw), state.is_full());
+        }
+    }
+
+    // outcomes are for the side to move, which after a winning move is the player who lost
+    #[test]
+    fn the_player_who_just_moved_wins_as_the_opponent() {
+        let before_win = State::from_moves(&[3, 2, 3, 2, 3, 2]).unwrap();
+        let won = before_win.play_move(3);
+
+        assert_eq!(before_win.outcome(), None);
+        assert!(!before_win.is_terminal());
+        assert_eq!(won.outcome(), Some(Outcome::OpponentWon));
+        assert!(won.is_terminal());
+
+        // only from a position no game reaches, with the pieces swapped
+        let swapped = State { curr_pieces: won.opp_pieces, opp_pieces: won.curr_pieces, ..won };
+        assert_eq!(swapped.outcome(), Some(Outcome::CurrentPlayerWon));
+    }
+
+    #[test]
+    fn legal_moves_skip_full_columns_in_left_to_right_order() {
+        let full_first_and_last = State::from_moves(&[0, 6, 0, 6, 0, 6, 6, 0, 6, 0, 6, 0, 0, 6]).unwrap();
+
+        assert_eq!(State::start_state().legal_moves(), [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(full_first_and_last.legal_moves(), [1, 2, 3, 4, 5]);
+        assert!(!full_first_and_last.is_legal(0));
+        assert!(!full_first_and_last.is_legal(6));
+        assert!(full_first_and_last.is_legal(5));
+        assert!(matches!(full_first_and_last.try_play_move(6), Err(Connect4Error::IllegalMove { col: 6 })));
+    }
+
+    #[test]
+    fn columns_off_the_board_are_never_legal() {
+        let start = State::start_state();
+
+        assert!(start.is_legal(COLS - 1));
+        assert!(!start.is_legal(COLS));
+        assert!(!start.is_legal(u32::MAX));
+    }
+
+    #[test]
+    fn a_finished_game_has_no_legal_moves() {
+        let won = State::from_moves(&[3, 2, 3, 2, 3, 2, 3]).unwrap();
+
+        assert!(won.legal_moves().is_empty());
+        assert!((0..COLS).all(|col| !won.is_legal(col)));
+    }
+
+    const STANDARD: BoardConfig = BoardConfig::STANDARD;
+
+    #[test]
+    fn standard_boards_encode_and_decode_with_six_rows() {
+        let board = [
+            "       ",
+            "       ",
+            "   O   ",
+            "   X   ",
+            "  OX   ",
+            " XOXO  ",
+        ];
+        let state = State::try_encode_with(&STANDARD, &board).unwrap();
+
+        assert_eq!(state.moves_made, 8);
+        assert_eq!(state.decode_with(&STANDARD).lines().collect::<Vec<_>>(), board);
+        assert_eq!(State::from_bitboard_with(&STANDARD, state.to_bitboard()), state);
+        assert!(State::from_parts_with(&STANDARD, state.curr_pieces, state.opp_pieces, state.height_map, 8).is_ok());
+        assert_eq!(STANDARD.max_total_moves(), 42);
+    }
+
+    #[test]
+    fn standard_boards_reject_a_seventh_row() {
+        let seven_rows = ["       "; 7];
+        assert!(matches!(State::try_encode_with(&STANDARD, &seven_rows), Err(Connect4Error::InvalidBoard(_))));
+
+        // a full 7 row column is fine on the default board and one too high on a standard one
+        let full_column = State::from_moves(&[0; 7]).unwrap();
+        assert!(State::try_from_bitboard(full_column.to_bitboard()).is_ok());
+        assert!(matches!(
+            State::try_from_bitboard_with(&STANDARD, full_column.to_bitboard()),
+            Err(Connect4Error::InvalidBitboard { .. })
+        ));
+        assert!(matches!(
+            State::from_parts_with(&STANDARD, full_column.curr_pieces, full_column.opp_pieces, full_column.height_map, 7),
+            Err(Connect4Error::InconsistentState(_))
+        ));
+    }
+
+    #[test]
+    fn standard_boards_fill_a_column_at_six_pieces() {
+        let full_column = State::from_moves_with(&STANDARD, &[0; 6]).unwrap();
+
+        assert!(!full_column.is_legal_with(&STANDARD, 0));
+        assert_eq!(full_column.legal_moves_with(&STANDARD), [1, 2, 3, 4, 5, 6]);
+        assert!(matches!(
+            State::from_moves_with(&STANDARD, &[0; 7]),
+            Err(Connect4Error::IllegalMoveAt { index: 6, col: 0 })
+        ));
+        assert!(full_column.is_legal(0));
+    }
+
+    #[test]
+    fn standard_start_state_and_reflection() {
+        let start = State::start_state_with(&STANDARD);
+
+        assert_eq!(start, State::encode_with(&STANDARD, vec!["       "; 6]));
+        assert_eq!(reflect_bitboard_with(&STANDARD, 1), 1 << col_shift!(6));
+    }
+
+    // the reachability check follows the config's win length: both sides having four in a row can't happen when
+    // four wins, but is just a position when it takes five
+    #[test]
+    fn try_encode_with_checks_wins_on_the_config() {
+        let board = [
+            "       ",
+            "       ",
+            "       ",
+            "       ",
+            "OOOO   ",
+            "XXXX   ",
+        ];
+
+        assert!(State::try_encode_with(&STANDARD, &board).is_err());
+        assert!(State::try_encode_with(&STANDARD.with_win_len(5), &board).is_ok());
+    }
+
+    // no game can end in under 7 plies, so every column is open at every one of the first 6
+    #[test]
+    fn perft_from_the_start_counts_every_move_sequence() {
+        let counts: Vec<u64> = (1..=6).map(|depth| perft(&State::start_state(), depth)).collect();
+
+        assert_eq!(counts, [7, 49, 343, 2401, 16807, 117649]);
+    }
+
+    #[test]
+    fn perft_stops_at_a_finished_game() {
+        let won = State::from_moves(&[3, 2, 3, 2, 3, 2, 3]).unwrap();
+
+        assert_eq!(perft(&won, 5), 1);
+    }
+
+    // X wins with column 3, which ends that line one ply early: 6 * 7 + 1 sequences of two plies
+    #[test]
+    fn perft_counts_a_winning_move_as_one_leaf() {
+        let before_win = State::from_moves(&[3, 2, 3, 2, 3, 2]).unwrap();
+
+        assert_eq!(perft(&before_win, 1), 7);
+        assert_eq!(perft(&before_win, 2), 43);
+    }
+
+    // there's no serde format among the dependencies, so these serialize into Captured with just enough of a
+    // Serializer for State, and read it back through serde's own value deserializers
+    #[cfg(feature = "serde")]
+    mod serde_round_trip {
+        use super::*;
+        use serde::de::value::{Error, MapDeserializer};
+        use serde::de::IntoDeserializer;
+        use serde::ser::{Error as _, Impossible, SerializeStruct};
+        use serde::{Deserialize, Serialize, Serializer};
+
+        enum Captured {
+            Int(u64),
+            Str(String),
+            Fields(Vec<(&'static str, u64)>),
+        }
+
+        struct Capture;
+
+        struct CaptureFields(Vec<(&'static str, u64)>);
+
+        macro_rules! unsupported {
+            ($($method:ident($($ty:ty),*) -> $ret:ty;)*) => {
+                $(fn $method(self, $(_: $ty),*) -> core::result::Result<$ret, Error> {
+                    Err(Error::custom(stringify!($method)))
+                })*
+            };
+        }
+
+        impl Serializer for Capture {
+            type Ok = Captured;
+            type Error = Error;
+            type SerializeSeq = Impossible<Captured, Error>;
+            type SerializeTuple = Impossible<Captured, Error>;
+            type SerializeTupleStruct = Impossible<Captured, Error>;
+            type SerializeTupleVariant = Impossible<Captured, Error>;
+            type SerializeMap = Impossible<Captured, Error>;
+            type SerializeStruct = CaptureFields;
+            type SerializeStructVariant = Impossible<Captured, Error>;
+
+            fn serialize_i8(self, v: i8) -> core::result::Result<Captured, Error> {
+                u64::try_from(v).map(Captured::Int).map_err(Error::custom)
+            }
+
+            fn serialize_u64(self, v: u64) -> core::result::Result<Captured, Error> {
+                Ok(Captured::Int(v))
+            }
+
+            fn serialize_str(self, v: &str) -> core::result::Result<Captured, Error> {
+                Ok(Captured::Str(v.to_string()))
+            }
+
+            fn serialize_struct(self, _: &'static str, len: usize) -> core::result::Result<CaptureFields, Error> {
+                Ok(CaptureFields(Vec::with_capacity(len)))
+            }
+
+            fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> core::result::Result<Captured, Error> {
+                Err(Error::custom("serialize_some"))
+            }
+
+            fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _: &'static str, _: &T)
+                -> core::result::Result<Captured, Error> {
+                Err(Error::custom("serialize_newtype_struct"))
+            }
+
+            fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _: &'static str, _: u32, _: &'static str, _: &T)
+                -> core::result::Result<Captured, Error> {
+                Err(Error::custom("serialize_newtype_variant"))
+            }
+
+            unsupported! {
+                serialize_bool(bool) -> Captured;
+                serialize_i16(i16) -> Captured;
+                serialize_i32(i32) -> Captured;
+                serialize_i64(i64) -> Captured;
+                serialize_u8(u8) -> Captured;
+                serialize_u16(u16) -> Captured;
+                serialize_u32(u32) -> Captured;
+                serialize_f32(f32) -> Captured;
+                serialize_f64(f64) -> Captured;
+                serialize_char(char) -> Captured;
+                serialize_bytes(&[u8]) -> Captured;
+                serialize_none() -> Captured;
+                serialize_unit() -> Captured;
+                serialize_unit_struct(&'static str) -> Captured;
+                serialize_unit_variant(&'static str, u32, &'static str) -> Captured;
+                serialize_seq(Option<usize>) -> Self::SerializeSeq;
+                serialize_tuple(usize) -> Self::SerializeTuple;
+                serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+                serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+                serialize_map(Option<usize>) -> Self::SerializeMap;
+                serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+            }
+        }
+
+        impl SerializeStruct for CaptureFields {
+            type Ok = Captured;
+            type Error = Error;
+
+            fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T)
+                -> core::result::Result<(), Error> {
+                let Captured::Int(v) = value.serialize(Capture)? else {
+                    return Err(Error::custom(format!("{key} isn't an integer")));
+                };
+
+                self.0.push((key, v));
+
+                Ok(())
+            }
+
+            fn end(self) -> core::result::Result<Captured, Error> {
+                Ok(Captured::Fields(self.0))
+            }
+        }
+
+        fn round_trip(state: &State) -> State {
+            let Captured::Fields(fields) = state.serialize(Capture).unwrap() else { panic!("State isn't a struct") };
+            let names: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+
+            assert_eq!(names, ["curr_pieces", "opp_pieces", "height_map", "moves_made"]);
+
+            State::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter())).unwrap()
+        }
+
+        fn board_string_round_trip(state: &State) -> State {
+            let Captured::Str(board) = board_string::serialize(state, Capture).unwrap() else { panic!("not a str") };
+
+            assert_eq!(board, state.decode());
+
+            let state: core::result::Result<State, Error> =
+                board_string::deserialize(board.as_str().into_deserializer());
+
+            state.unwrap()
+        }
+
+        #[test]
+        fn states_round_trip_through_their_fields() {
+            for state in [State::start_state(), State::from_moves(&[3, 3, 4, 2, 0, 6, 6]).unwrap()] {
+                assert_eq!(round_trip(&state), state);
+            }
+        }
+
+        #[test]
+        fn states_round_trip_through_their_board() {
+            for state in [State::start_state(), State::from_moves(&[3, 3, 4, 2, 0, 6, 6]).unwrap()] {
+                assert_eq!(board_string_round_trip(&state), state);
+            }
+        }
+
+        // a board with a piece floating above an empty cell
+        #[test]
+        fn board_string_rejects_boards_try_encode_rejects() {
+            let board = "       \n       \n       \n       \n       \n   X   \n       \n";
+            let result: core::result::Result<State, Error> = board_string::deserialize(board.into_deserializer());
+
+            assert!(result.is_err());
+        }
+
+        proptest! {
+            #[test]
+            fn reachable_states_round_trip(state in reachable_state()) {
+                prop_assert_eq!(round_trip(&state), state.clone());
+                prop_assert_eq!(board_string_round_trip(&state), state);
+            }
+        }
+    }
+}
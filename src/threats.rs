@@ -1,8 +1,11 @@
+use crate::engine::{CONNECTION_DIRECTIONS, DEFAULT_MOVE_ORDER, WIN_LENGTH};
 use crate::col_shift;
-use crate::engine::{is_win, DEFAULT_MOVE_ORDER};
-use crate::state::{COLS, COL_MASK};
+use crate::state::{Board, DefaultBoard, COLS, COL_BITS, COL_MASK};
+use core::cmp::Reverse;
 
 pub const FOUR_BIT_MASK: u32 = 0b1111;
+// a run can't be longer than the widest board, which is 64 / COL_BITS columns
+const MAX_WIN_LEN: usize = u64::BITS as usize / COL_BITS;
 
 #[macro_export]
 macro_rules! index {
@@ -48,14 +51,38 @@ macro_rules! slice_shift {
 }
 
 pub fn sort_by_threats(col_threats: u32) -> u32 {
-    let mut move_order = DEFAULT_MOVE_ORDER;
+    sort_by_threats_from(DEFAULT_MOVE_ORDER, col_threats)
+}
+
+pub fn sort_by_threats_from(move_order: u32, col_threats: u32) -> u32 {
+    sort_by_key(move_order, |col| get!(col_threats, col))
+}
+
+// history only decides between mirrored columns with the same number of threats, breaking ties any more
+// broadly overrides the base order (central columns first by default), which costs far more nodes than it saves
+pub fn sort_by_threats_and_history(move_order: u32, col_threats: u32, history: &[u32; COLS as usize]) -> u32 {
+    let mut pair_rank = [0; COLS as usize];
+
+    // a column and its mirror both rank where the first of them comes in the base order
+    for i in (0..COLS).rev() {
+        let col = get!(move_order, i);
+        pair_rank[col as usize] = i;
+        pair_rank[(COLS - 1 - col) as usize] = i;
+    }
+
+    sort_by_key(move_order, |col| {
+        (get!(col_threats, col), Reverse(pair_rank[col as usize]), history[col as usize])
+    })
+}
 
+// stable insertion sort of move_order by descending key, so equal keys keep their order in move_order
+fn sort_by_key<K: Ord>(mut move_order: u32, key: impl Fn(u32) -> K) -> u32 {
     for i in 0..COLS {
         let curr_col = get!(move_order, i);
-        let curr_threats = get!(col_threats, curr_col);
+        let curr_key = key(curr_col);
         let mut j = i;
 
-        while j > 0 && curr_threats > get!(col_threats, get!(move_order, j - 1)) {
+        while j > 0 && curr_key > key(get!(move_order, j - 1)) {
             j -= 1;
         }
 
@@ -67,20 +94,185 @@ pub fn sort_by_threats(col_threats: u32) -> u32 {
     move_order
 }
 
-pub fn count_threats(pieces: u64, height_map: u64) -> u32 {
-    let mut threat_count = 0;
-    
-    for col in 0..COLS {
-        
-        let col_mask = COL_MASK << col_shift!(col);
-        let limit = col_mask >> 1;
-        
-        let mut cell = height_map & col_mask;
-        while cell < limit {
-            threat_count += is_win(pieces | cell) as u32;
-            cell <<= 1;
+// moves col to position index of move_order, keeping the relative order of everything else; a col that
+// already comes at or before index stays where it is
+pub fn promote_col(move_order: u32, col: u32, index: u32) -> u32 {
+    for i in index + 1..COLS {
+        if get!(move_order, i) == col {
+            return slice_clear!(move_order, index, i + 1)
+                | (col << index!(index))
+                | slice_shift!(move_order, index, i, index + 1);
+        }
+    }
+
+    move_order
+}
+
+// every legal cell, filled or not, that would complete a run of win_len with pieces; a run through a
+// sentinel row or past the edge of the board only lands on cells outside IS_LEGAL, so masking drops it
+pub fn threat_squares(pieces: u64, win_len: u32) -> u64 {
+    threat_squares_on(DefaultBoard, pieces, win_len)
+}
+
+pub fn threat_squares_on<B: Board>(board: B, pieces: u64, win_len: u32) -> u64 {
+    let is_legal = board.config().is_legal_mask();
+    let pieces = pieces & is_legal;
+    let mut threats = 0;
+
+    for &direction in CONNECTION_DIRECTIONS {
+        // below[k] / above[k]: cells with k pieces in a row directly before / after them in this direction
+        let mut below = [u64::MAX; MAX_WIN_LEN];
+        let mut above = [u64::MAX; MAX_WIN_LEN];
+
+        for k in 1..win_len as usize {
+            below[k] = below[k - 1] & (pieces << (k * direction));
+            above[k] = above[k - 1] & (pieces >> (k * direction));
+        }
+
+        for k in 0..win_len as usize {
+            threats |= below[k] & above[win_len as usize - 1 - k];
+        }
+    }
+
+    threats & is_legal
+}
+
+// the open cells where the owner of pieces wins by dropping a piece right now
+pub fn immediate_threats(pieces: u64, height_map: u64) -> u64 {
+    immediate_threats_on(DefaultBoard, pieces, height_map)
+}
+
+// the threats to complete board's win_len in a row
+pub fn immediate_threats_on<B: Board>(board: B, pieces: u64, height_map: u64) -> u64 {
+    let config = board.config();
+
+    threat_squares_on(board, pieces, config.win_len) & height_map & config.is_legal_mask()
+}
+
+// threats on empty cells anywhere above the height map, for pieces that don't already contain a win
+pub fn count_threats(pieces: u64, height_map: u64, win_len: u32) -> u32 {
+    count_threats_on(DefaultBoard, pieces, height_map, win_len)
+}
+
+pub fn count_threats_on<B: Board>(board: B, pieces: u64, height_map: u64, win_len: u32) -> u32 {
+    (threat_squares_on(board, pieces, win_len) & empty_cells(board, height_map)).count_ones()
+}
+
+// count_threats split up by the column each threat square is in
+pub fn column_threats(pieces: u64, height_map: u64) -> [u32; COLS as usize] {
+    let threats = threat_squares(pieces, WIN_LENGTH) & empty_cells(DefaultBoard, height_map);
+
+    core::array::from_fn(|col| ((threats >> col_shift!(col)) & COL_MASK).count_ones())
+}
+
+// the four bits per column layout sort_by_threats reads, counts past FOUR_BIT_MASK are capped so they can't
+// spill into the next column
+pub fn pack_threats(col: u32, threats: u32) -> u32 {
+    threats.min(FOUR_BIT_MASK) << index!(col)
+}
+
+// each column's height bit and everything above it up to the last row; the sentinel row is outside IS_LEGAL, so
+// a run pointing off the top of a full or nearly full column is never counted as a threat
+fn empty_cells<B: Board>(board: B, height_map: u64) -> u64 {
+    let is_legal = board.config().is_legal_mask();
+    let bottom_row = is_legal & !(is_legal << 1);
+
+    is_legal & !(height_map - bottom_row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{is_win, IS_LEGAL};
+    use crate::state::State;
+
+    fn xorshift(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    // the height map cells, one per open column, that win when either side drops a piece there
+    fn naive_immediate_threats(pieces: u64, height_map: u64) -> u64 {
+        (0..COLS)
+            .map(|col| height_map & (COL_MASK << col_shift!(col)) & IS_LEGAL)
+            .filter(|&cell| cell != 0 && is_win(pieces | cell, WIN_LENGTH))
+            .fold(0, |threats, cell| threats | cell)
+    }
+
+    #[test]
+    fn immediate_threats_agree_with_the_counted_threats() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15;
+
+        for _ in 0..200 {
+            let mut state = State::start_state();
+
+            while !state.is_terminal() {
+                for pieces in [state.curr_pieces, state.opp_pieces] {
+                    let threats = immediate_threats(pieces, state.height_map);
+                    let column_threats = column_threats(pieces, state.height_map);
+
+                    assert_eq!(threats, naive_immediate_threats(pieces, state.height_map));
+                    assert_eq!(threats, threat_squares(pieces, WIN_LENGTH) & state.height_map & IS_LEGAL);
+                    assert!(threats.count_ones() <= count_threats(pieces, state.height_map, WIN_LENGTH));
+                    assert!((0..COLS).all(|col| (threats >> col_shift!(col)) & COL_MASK == 0
+                        || column_threats[col as usize] > 0));
+                }
+
+                let legal_moves = state.legal_moves();
+                state = state.play_move(legal_moves[xorshift(&mut seed) as usize % legal_moves.len()]);
+            }
         }
     }
 
-    threat_count
+    fn cell(row: u32, col: u32) -> u64 {
+        1 << (row + col_shift!(col))
+    }
+
+    // X along the bottom row in columns 2 to 4, open at both ends
+    #[test]
+    fn an_open_three_threatens_the_columns_either_side() {
+        let pieces = cell(0, 2) | cell(0, 3) | cell(0, 4);
+        let height_map = cell(0, 0) | cell(0, 1) | cell(1, 2) | cell(1, 3) | cell(1, 4) | cell(0, 5) | cell(0, 6);
+        let threats = column_threats(pieces, height_map);
+
+        assert_eq!(threats, [0, 1, 0, 0, 0, 1, 0]);
+        assert_eq!(threats.iter().sum::<u32>(), count_threats(pieces, height_map, WIN_LENGTH));
+
+        let packed = (0..COLS).fold(0, |packed, col| packed | pack_threats(col, threats[col as usize]));
+        let move_order = sort_by_threats(packed);
+
+        assert!((0..COLS).all(|col| get!(packed, col) == threats[col as usize]));
+        // the two threatening columns first, then the rest in DEFAULT_MOVE_ORDER
+        assert_eq!(core::array::from_fn(|i| get!(move_order, i as u32)), [5, 1, 3, 2, 4, 6, 0]);
+    }
+
+    #[test]
+    fn packed_counts_are_capped_at_four_bits() {
+        assert_eq!(pack_threats(2, 20), FOUR_BIT_MASK << index!(2));
+    }
+
+    // runs that would end one row above the board land in the sentinel row, which no piece can fill
+    #[test]
+    fn runs_pointing_off_the_top_of_a_column_are_not_threats() {
+        let bottom_row = |cols: core::ops::Range<u32>| cols.fold(0, |height_map, col| height_map | cell(0, col));
+
+        // three at the top of a full column, completed only by the filled cell below them
+        let vertical = cell(4, 3) | cell(5, 3) | cell(6, 3);
+        let height_map = bottom_row(0..3) | cell(7, 3) | bottom_row(4..COLS);
+        assert_eq!(threat_squares(vertical, WIN_LENGTH), cell(3, 3));
+        assert_eq!(count_threats(vertical, height_map, WIN_LENGTH), 0);
+
+        // a diagonal up to the top row, whose next cell would be in the sentinel row of column 3
+        let diagonal = cell(4, 0) | cell(5, 1) | cell(6, 2);
+        let height_map = bottom_row(0..3) | cell(6, 3) | bottom_row(4..COLS);
+        assert_eq!(count_threats(diagonal, height_map, WIN_LENGTH), 0);
+        assert_eq!(column_threats(diagonal, height_map), [0; COLS as usize]);
+
+        // three along the top row still threatens the top cell of the near-full column beside it
+        let top_row = cell(6, 0) | cell(6, 1) | cell(6, 2);
+        assert_eq!(count_threats(top_row, height_map, WIN_LENGTH), 1);
+        assert_eq!(immediate_threats(top_row, height_map), cell(6, 3));
+    }
 }
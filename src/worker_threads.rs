@@ -1,43 +1,105 @@
 use crate::caches::StateCaches;
-use crate::engine::{evaluate_position_rec, optimal_moves, optimal_moves_with_workers, MAX_EVAL, MIN_EVAL};
+use crate::cancellation::CancellationToken;
+use crate::engine::{evaluate_position_rec, optimal_moves_to_depth, FULL_DEPTH, MAX_EVAL, MIN_EVAL};
 use crate::error::{Connect4Error, Result};
 use crate::state::State;
-use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use log::debug;
+use std::any::Any;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
 
 
-pub const DEFAULT_NUM_WORKER_THREADS: usize = 31;
+pub const MIN_NUM_WORKER_THREADS: usize = 1;
+pub const MAX_NUM_WORKER_THREADS: usize = 63;
+
+// one worker per core besides the calling thread, which searches as well
+pub fn default_num_worker_threads() -> usize {
+    let parallelism = thread::available_parallelism().map_or(1, |parallelism| parallelism.get());
+
+    (parallelism - 1).clamp(MIN_NUM_WORKER_THREADS, MAX_NUM_WORKER_THREADS)
+}
+
+// fully populated before the workers start, so claiming a state is a single atomic increment
+pub struct StateQueue {
+    states: Vec<State>,
+    next: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+impl StateQueue {
+    pub fn new(states: Vec<State>) -> Self {
+        Self {
+            states,
+            next: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn pop(&self) -> Option<&State> {
+        self.states.get(self.next.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn complete(&self) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn completed(&self) -> usize {
+        self.completed.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
 
 pub struct WorkerThreadHandler {
-    terminate_flag: Arc<AtomicBool>,
+    cancel: CancellationToken,
     join_handle: JoinHandle<Result<usize>>,
 }
 
 impl WorkerThreadHandler {
     pub fn terminate(&self) {
-        self.terminate_flag.store(true, Ordering::Relaxed);
+        self.cancel.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.is_finished()
     }
 
     pub fn join(self) -> Result<usize> {
-        self.join_handle.join().map_err(|_| Connect4Error::WorkerThreadJoinError)?
+        self.join_handle.join().map_err(worker_thread_panic)?
     }
 }
 
+pub fn worker_thread_panic(payload: Box<dyn Any + Send>) -> Connect4Error {
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    };
+
+    Connect4Error::WorkerThreadPanic(message)
+}
+
 fn evaluate_position_worker_thread(
     game_state: State,
-    caches: &StateCaches
+    caches: &StateCaches,
+    cancel: CancellationToken
 ) -> WorkerThreadHandler {
 
-    let mut thread_caches = caches.with_same_beg_caches();
-
-    let terminate_flag = Arc::new(AtomicBool::new(false));
-    let terminate_flag_clone = terminate_flag.clone();
+    let mut thread_caches = caches.with_shared_caches();
+    let thread_cancel = cancel.clone();
 
     let join_handle = thread::spawn(move || {
-        println!("Evaluate Position Worker Thread Started");
+        debug!("Evaluate Position Worker Thread Started");
 
         let mut pos = 0;
 
@@ -48,8 +110,10 @@ fn evaluate_position_worker_thread(
             game_state.moves_made,
             MIN_EVAL,
             MAX_EVAL,
+            FULL_DEPTH,
             &mut thread_caches,
-            &terminate_flag_clone,
+            thread_cancel.flag(),
+            &mut false,
             &mut pos,
         );
 
@@ -57,60 +121,74 @@ fn evaluate_position_worker_thread(
     });
 
     WorkerThreadHandler {
+        cancel,
         join_handle,
-        terminate_flag,
     }
 }
 
+// each worker searches a distinct position WORKER_THREAD_DEPTH moves ahead, so fewer workers than requested
+// are seeded when the position has fewer successors; sorted by canonical bitboard so the same position always
+// seeds the same workers in the same order, which keeps node counts comparable between runs
+pub fn worker_seed_states(num_workers: usize, game_state: &State) -> Vec<State> {
+    const WORKER_THREAD_DEPTH: usize = 1;
+    let game_state_bitboard = game_state.to_bitboard();
+
+    let mut bitboards: Vec<u64> = game_state.states_iter(WORKER_THREAD_DEPTH)
+        .filter(|&bitboard| bitboard != game_state_bitboard)
+        .take(num_workers)
+        .collect();
+
+    bitboards.sort_unstable();
+
+    bitboards.into_iter().map(State::from_bitboard).collect()
+}
+
+// every worker gets a child of cancel, so cancelling it stops them all and terminate stops one
 pub fn spawn_evaluate_position_worker_threads(
     num_workers: usize,
     game_state: &State,
-    caches: &StateCaches
+    caches: &StateCaches,
+    cancel: &CancellationToken
 ) -> Vec<WorkerThreadHandler> {
 
-    const WORKER_THREAD_DEPTH: usize = 1;
-    let states = game_state.generate_states(WORKER_THREAD_DEPTH);
+    let handlers: Vec<WorkerThreadHandler> = worker_seed_states(num_workers, game_state).into_iter()
+        .map(|state| evaluate_position_worker_thread(state, caches, cancel.child()))
+        .collect();
 
-    let mut handlers = vec![];
-
-    for (_, bitboard) in (0..num_workers).zip(states) {
-        let state = State::from_bitboard(bitboard);
-        if &state == game_state {
-            continue;
-        }
-
-        handlers.push(evaluate_position_worker_thread(state, caches));
-    };
-
-    println!("Worker Thread Count: {}", handlers.len());
+    debug!("Worker Thread Count: {}", handlers.len());
 
     handlers
 }
 
 fn database_generator_worker_thread(
-    states: Arc<Mutex<VecDeque<State>>>,
-    caches: &StateCaches
+    states: Arc<StateQueue>,
+    caches: &StateCaches,
+    cancel: CancellationToken
 ) -> WorkerThreadHandler {
 
-    let mut thread_caches = caches.with_same_beg_caches();
+    let mut thread_caches = caches.fork_shared_begin();
+    let thread_cancel = cancel.clone();
 
     let join_handle = thread::spawn(move || {
-        println!("Database Generator Worker Thread Started");
+        debug!("Database Generator Worker Thread Started");
 
         let mut pos = 0;
 
-        loop {
-            let state_opt = {
-                let mut positions_lock = states.lock().unwrap();
-                positions_lock.pop_front()
-            };
-
-            match state_opt {
+        while !thread_cancel.is_cancelled() {
+            match states.pop() {
                 Some(state) => {
-                    let (eval, _) = optimal_moves(
-                        &state, &mut thread_caches, &mut pos)?;
+                    let best_moves = optimal_moves_to_depth(
+                        state, FULL_DEPTH, None, &mut thread_caches, thread_cancel.flag(), &mut false, &mut pos);
 
-                    thread_caches.put_beg_game_lower_bound(eval, state.to_bitboard());
+                    match best_moves {
+                        Ok((eval, _)) => {
+                            thread_caches.put_beg_game_lower_bound(eval, state.to_bitboard());
+                            thread_caches.put_beg_game_upper_bound(eval, state.to_bitboard());
+                            states.complete();
+                        },
+                        Err(_) if thread_cancel.is_cancelled() => break,
+                        Err(err) => return Err(err),
+                    }
                 },
                 None => break,
             };
@@ -120,18 +198,83 @@ fn database_generator_worker_thread(
     });
 
     WorkerThreadHandler {
-        terminate_flag: Arc::new(AtomicBool::new(false)),
+        cancel,
         join_handle,
     }
 }
 
 pub fn spawn_database_generator_worker_threads(
     num_workers: usize,
-    states: Arc<Mutex<VecDeque<State>>>,
-    caches: &StateCaches
+    states: Arc<StateQueue>,
+    caches: &StateCaches,
+    cancel: &CancellationToken
 ) -> Vec<WorkerThreadHandler> {
 
     (0..num_workers).map(|_| {
-        database_generator_worker_thread(states.clone(), &caches)
+        database_generator_worker_thread(states.clone(), caches, cancel.child())
     }).collect()
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panicking_worker(panic: fn() -> Result<usize>) -> WorkerThreadHandler {
+        WorkerThreadHandler {
+            cancel: CancellationToken::new(),
+            join_handle: thread::spawn(panic),
+        }
+    }
+
+    #[test]
+    fn join_surfaces_a_formatted_panic_message() {
+        let handler = panicking_worker(|| panic!("cache index {} out of range", 7));
+
+        match handler.join() {
+            Err(Connect4Error::WorkerThreadPanic(message)) => assert_eq!(message, "cache index 7 out of range"),
+            result => panic!("expected a worker panic, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn join_surfaces_a_static_panic_message() {
+        let handler = panicking_worker(|| panic!("worker died"));
+
+        match handler.join() {
+            Err(Connect4Error::WorkerThreadPanic(message)) => assert_eq!(message, "worker died"),
+            result => panic!("expected a worker panic, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_payloads_still_become_worker_panics() {
+        let error = worker_thread_panic(Box::new(7));
+
+        assert!(matches!(error, Connect4Error::WorkerThreadPanic(message) if message == "unknown panic payload"));
+    }
+
+    // each call walks the successors with a freshly seeded HashSet, so a seed set that depended on hash order
+    // would differ between calls
+    #[test]
+    fn the_seeded_workers_are_the_same_for_the_same_position() {
+        let state = State::from_moves(&[3, 3, 2, 4]).unwrap();
+        let seeds = worker_seed_states(4, &state);
+
+        assert_eq!(seeds.len(), 4);
+        assert!(seeds.windows(2).all(|pair| pair[0].to_bitboard() < pair[1].to_bitboard()));
+
+        for _ in 0..10 {
+            assert_eq!(worker_seed_states(4, &state), seeds);
+        }
+    }
+
+    #[test]
+    fn every_distinct_successor_is_seeded_when_there_are_workers_for_them() {
+        let seeds: Vec<u64> = worker_seed_states(16, &State::start_state()).iter().map(State::to_bitboard).collect();
+        let mut successors: Vec<u64> = (0..4).map(|col| State::from_moves(&[col]).unwrap().to_bitboard()).collect();
+        successors.sort_unstable();
+
+        assert_eq!(seeds, successors);
+    }
+}
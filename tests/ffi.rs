@@ -0,0 +1,81 @@
+// the C ABI called the way a C program would, through the declarations in include/connect4.h rather than
+// the Rust functions behind them
+use connect4 as _;
+use std::ffi::c_int;
+
+
+const C4_OK: c_int = 0;
+const C4_ILLEGAL_MOVE: c_int = 1;
+const C4_NO_MOVE: c_int = -1;
+const C4_ONGOING: c_int = 0;
+const C4_X_WON: c_int = 1;
+const C4_O_WON: c_int = 2;
+
+#[repr(C)]
+struct C4State {
+    _private: [u8; 0],
+}
+
+unsafe extern "C" {
+    fn c4_new() -> *mut C4State;
+    fn c4_free(state: *mut C4State);
+    fn c4_play(state: *mut C4State, col: c_int) -> c_int;
+    fn c4_best_move(state: *const C4State) -> c_int;
+    fn c4_outcome(state: *const C4State) -> c_int;
+}
+
+fn play_all(state: *mut C4State, cols: &[c_int]) {
+    for &col in cols {
+        assert_eq!(unsafe { c4_play(state, col) }, C4_OK, "column {col}");
+    }
+}
+
+#[test]
+fn a_game_played_through_the_c_abi_ends_in_a_win() {
+    let state = unsafe { c4_new() };
+    assert!(!state.is_null());
+
+    play_all(state, &[3, 2, 3, 2, 3, 2]);
+    assert_eq!(unsafe { c4_outcome(state) }, C4_ONGOING);
+    assert_eq!(unsafe { c4_best_move(state) }, 3);
+
+    play_all(state, &[3]);
+    assert_eq!(unsafe { c4_outcome(state) }, C4_X_WON);
+    assert_eq!(unsafe { c4_best_move(state) }, C4_NO_MOVE);
+    assert_eq!(unsafe { c4_play(state, 0) }, C4_ILLEGAL_MOVE);
+
+    unsafe { c4_free(state) };
+}
+
+#[test]
+fn illegal_moves_leave_the_state_as_it_was() {
+    let state = unsafe { c4_new() };
+
+    for col in [-1, 7, c_int::MAX] {
+        assert_eq!(unsafe { c4_play(state, col) }, C4_ILLEGAL_MOVE, "column {col}");
+    }
+
+    // alternating all the way up, so column 0 fills without a winner
+    play_all(state, &[0; 7]);
+    assert_eq!(unsafe { c4_play(state, 0) }, C4_ILLEGAL_MOVE);
+    assert_eq!(unsafe { c4_outcome(state) }, C4_ONGOING);
+
+    play_all(state, &[1]);
+    unsafe { c4_free(state) };
+}
+
+#[test]
+fn the_outcome_names_the_winner_rather_than_the_side_to_move() {
+    let state = unsafe { c4_new() };
+
+    // O completes columns 1 to 4 along the bottom row with X to move
+    play_all(state, &[6, 1, 6, 2, 5, 3, 0, 4]);
+    assert_eq!(unsafe { c4_outcome(state) }, C4_O_WON);
+
+    unsafe { c4_free(state) };
+}
+
+#[test]
+fn freeing_null_is_a_no_op() {
+    unsafe { c4_free(std::ptr::null_mut()) };
+}